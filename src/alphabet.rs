@@ -0,0 +1,109 @@
+use serde::{Deserialize, Serialize};
+use crate::solutions::TransformHash;
+
+/// A set of lowercase letters, one bit each
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Alphabet(u32);
+
+impl Alphabet {
+    pub const ALL: Alphabet = Alphabet((1 << 26) - 1);
+
+    pub fn of(letters: impl IntoIterator<Item = char>) -> Alphabet {
+        Alphabet(letters.into_iter()
+            .filter(char::is_ascii_lowercase)
+            .fold(0, |bits, c| bits | 1 << (c as u8 - b'a')))
+    }
+
+    pub fn contains(&self, c: char) -> bool {
+        c.is_ascii_lowercase() && self.0 & 1 << (c as u8 - b'a') != 0
+    }
+
+    pub fn intersect(self, other: Alphabet) -> Alphabet {
+        Alphabet(self.0 & other.0)
+    }
+
+    pub fn union(self, other: Alphabet) -> Alphabet {
+        Alphabet(self.0 | other.0)
+    }
+
+    pub fn without(self, other: Alphabet) -> Alphabet {
+        Alphabet(self.0 & !other.0)
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.count_ones() as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+
+    pub fn is_subset(&self, other: Alphabet) -> bool {
+        self.0 & !other.0 == 0
+    }
+
+    pub fn letters(&self) -> impl Iterator<Item = char> + '_ {
+        ('a'..='z').filter(|c| self.contains(*c))
+    }
+}
+
+/// A game rule about which letters may fill the blanks. Several rules all have to hold
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum BlankRule {
+    /// Only these letters
+    Only(String),
+    /// Any letter but these
+    Except(String),
+    /// No letter that is printed on the card
+    NotOnCard,
+    /// Only letters the transform takes off the card, its left side
+    TransformLeft,
+    /// Only letters the transform puts on the card, its right side
+    TransformRight,
+}
+
+impl BlankRule {
+    /// Parses "only:abc", "except:xyz", "not-on-card", "left" or "right"
+    pub fn parse(text: &str) -> Result<BlankRule, String> {
+        let letters = |letters: &str| -> Result<String, String> {
+            if letters.is_empty() || !letters.chars().all(|c| c.is_ascii_lowercase()) {
+                return Err(format!("blank rule \"{}\" needs some letters, like only:aeiou", text));
+            }
+            Ok(letters.to_string())
+        };
+        match text.split_once(':') {
+            Some(("only", rest)) => Ok(BlankRule::Only(letters(rest)?)),
+            Some(("except", rest)) => Ok(BlankRule::Except(letters(rest)?)),
+            None if text == "not-on-card" => Ok(BlankRule::NotOnCard),
+            None if text == "left" => Ok(BlankRule::TransformLeft),
+            None if text == "right" => Ok(BlankRule::TransformRight),
+            _ => Err(format!("unknown blank rule \"{}\", expected only:LETTERS, except:LETTERS, not-on-card, left or right", text)),
+        }
+    }
+
+    /// The letters the blanks may hold under every rule. `card` is the letters printed on the card. Without a
+    /// transform the per-transform rules are left out, giving the letters any transform could allow
+    pub fn alphabet(rules: &[BlankRule], card: &[char], transform: Option<&TransformHash>) -> Alphabet {
+        rules.iter().fold(Alphabet::ALL, |alphabet, rule| match (rule, transform) {
+            (BlankRule::Only(letters), _) => alphabet.intersect(Alphabet::of(letters.chars())),
+            (BlankRule::Except(letters), _) => alphabet.without(Alphabet::of(letters.chars())),
+            (BlankRule::NotOnCard, _) => alphabet.without(Alphabet::of(card.iter().copied())),
+            (BlankRule::TransformLeft, Some(transform)) => alphabet.intersect(Alphabet::of(transform.keys().copied())),
+            (BlankRule::TransformRight, Some(transform)) => alphabet.intersect(Alphabet::of(transform.values().copied())),
+            (_, None) => alphabet,
+        })
+    }
+}
+
+impl std::fmt::Display for BlankRule {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BlankRule::Only(letters) => write!(f, "only:{}", letters),
+            BlankRule::Except(letters) => write!(f, "except:{}", letters),
+            BlankRule::NotOnCard => write!(f, "not-on-card"),
+            BlankRule::TransformLeft => write!(f, "left"),
+            BlankRule::TransformRight => write!(f, "right"),
+        }
+    }
+}
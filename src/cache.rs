@@ -0,0 +1,816 @@
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::io::{BufRead, BufReader, BufWriter, Read, Write};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use serde::{Deserialize, Serialize};
+use crate::checkpoint::Checkpoint;
+use crate::dictionary::{Dictionary, Tier, WordInfo};
+use crate::progress::Progress;
+use crate::puzzle::Puzzle;
+use crate::solutions::{self, SearchError, Shard, SolverOptions, TransformAndPossibilitiesList, TransformHash, WordList};
+
+/// The version of the cache layout this build writes. 0 was a bare list of solutions, 1 added the puzzle and options,
+/// 2 moved those into a header line ahead of the data, 3 made the data binary
+pub const FORMAT_VERSION: u32 = 3;
+
+/// Where a cache's solutions came from. It's the first line of the file so it can be checked before the data is read
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CacheHeader {
+    pub format_version: u32,
+    pub puzzle: String,
+    /// `Dictionary::hash` of the word lists searched, unknown for caches older than the header
+    pub dictionary_hash: Option<String>,
+    pub options: SolverOptions,
+    /// Seconds since the unix epoch
+    pub created: Option<u64>,
+}
+
+impl CacheHeader {
+    pub fn new(puzzle: &str, dictionary_hash: Option<String>, options: SolverOptions) -> CacheHeader {
+        CacheHeader {
+            format_version: FORMAT_VERSION,
+            puzzle: puzzle.to_string(),
+            dictionary_hash,
+            options,
+            created: SystemTime::now().duration_since(UNIX_EPOCH).ok().map(|time| time.as_secs()),
+        }
+    }
+
+    /// How long ago the cache was written, like "3 days ago"
+    pub fn age(&self) -> Option<String> {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).ok()?.as_secs();
+        let seconds = now.saturating_sub(self.created?);
+        let (count, unit) = match seconds {
+            0..60 => return Some("just now".to_string()),
+            60..3600 => (seconds / 60, "minute"),
+            3600..86400 => (seconds / 3600, "hour"),
+            _ => (seconds / 86400, "day"),
+        };
+        Some(format!("{} {}{} ago", count, unit, if count == 1 { "" } else { "s" }))
+    }
+
+    /// Makes sure the cache answers the question being asked and returns its puzzle. `given` names the options the
+    /// caller actually asked for, as `SolverOptions::differences` calls them, and only those have to match. Blank
+    /// rules never have to, since a cache can be re-checked against those
+    pub fn check(&self, path: &str, puzzle: Option<&Puzzle>, options: &SolverOptions, given: &[&str]) -> Result<Puzzle, CacheError> {
+        let cached_puzzle = Puzzle::new(&self.puzzle)
+            .map_err(|e| CacheError::Corrupt { path: path.to_string(), reason: format!("invalid puzzle: {}", e) })?;
+        if let Some(requested) = puzzle && *requested != cached_puzzle {
+            return Err(CacheError::WrongPuzzle {
+                path: path.to_string(),
+                reason: format!("was built for a different puzzle:\n  cached:    {}\n  requested: {}", cached_puzzle, requested),
+            });
+        }
+        let differences = self.options.differences(options).into_iter()
+            .filter(|(name, _, _)| given.contains(name))
+            .map(|(name, cached, requested)| format!("  {}: cached {}, requested {}", name, cached, requested))
+            .collect::<Vec<_>>();
+        if !differences.is_empty() {
+            return Err(CacheError::WrongPuzzle {
+                path: path.to_string(),
+                reason: format!("was built with different options:\n{}", differences.join("\n")),
+            });
+        }
+        Ok(cached_puzzle)
+    }
+
+    /// Whether the word lists on disk are different now from when the cache was built. Caches that didn't record a
+    /// hash are taken to be up to date
+    pub fn dictionaries_changed(&self) -> Result<bool, Box<dyn Error>> {
+        match &self.dictionary_hash {
+            Some(hash) => Ok(Dictionary::hash_files(&self.options.dictionaries)? != *hash),
+            None => Ok(false),
+        }
+    }
+}
+
+/// What gets written to disk: the solutions along with the header saying how they were computed
+pub struct CacheFile {
+    pub header: CacheHeader,
+    pub solutions: TransformAndPossibilitiesList,
+    /// Which dictionary tier each word in the solutions came from and how common it is
+    pub words: WordInfo,
+}
+
+/// Why a cache couldn't be used
+#[derive(Debug)]
+pub enum CacheError {
+    /// Nothing at the path, so there's nothing to lose by searching
+    Missing(String),
+    /// The file is there but couldn't be read or decoded
+    Corrupt { path: String, reason: String },
+    /// Written by a newer build in a layout this one doesn't know
+    WrongVersion { path: String, found: u32 },
+    /// Built for a different puzzle, or with different options than were asked for
+    WrongPuzzle { path: String, reason: String },
+    /// Old enough not to record its puzzle, and no puzzle was given to go with it
+    NoPuzzle(String),
+}
+
+impl std::fmt::Display for CacheError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CacheError::Missing(path) => write!(f, "No solutions file at {}", path),
+            CacheError::Corrupt { path, reason } => write!(f, "Solutions file {} is damaged or unreadable: {}", path, reason),
+            CacheError::WrongVersion { path, found } => write!(f, "Solutions file {} is format version {}, but this build only reads up to version {}, update jipher to open it",
+                path, found, FORMAT_VERSION),
+            CacheError::WrongPuzzle { path, reason } => write!(f, "Solutions file {} {}", path, reason),
+            CacheError::NoPuzzle(path) => write!(f, "Solutions file {} does not record its puzzle, so it has to be given", path),
+        }
+    }
+}
+
+impl Error for CacheError {}
+
+/// Caches written before the puzzle was recorded are a bare list of solutions
+pub enum StoredCache {
+    Current(Box<CacheFile>),
+    Legacy(TransformAndPossibilitiesList),
+}
+
+// the part of the file after the header in version 2
+#[derive(Deserialize)]
+struct CacheData {
+    solutions: TransformAndPossibilitiesList,
+    #[serde(flatten)]
+    words: WordInfo,
+}
+
+// read on its own first, so a newer header we can't make sense of still gets a useful error
+#[derive(Deserialize)]
+struct Version {
+    format_version: u32,
+}
+
+// versions 0 and 1, which were a single JSON value with no header line
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Unversioned {
+    Options(Box<UnversionedCache>),
+    Bare(TransformAndPossibilitiesList),
+}
+
+#[derive(Deserialize)]
+struct UnversionedCache {
+    puzzle: String,
+    // caches from before the options were recorded were all built the legacy way
+    #[serde(default = "SolverOptions::legacy")]
+    options: SolverOptions,
+    solutions: TransformAndPossibilitiesList,
+    #[serde(flatten)]
+    words: WordInfo,
+}
+
+pub fn read(path: &str) -> Result<StoredCache, CacheError> {
+    let corrupt = |reason: &dyn std::fmt::Display| CacheError::Corrupt { path: path.to_string(), reason: reason.to_string() };
+    let file = match std::fs::File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Err(CacheError::Missing(path.to_string())),
+        Err(e) => return Err(corrupt(&e)),
+    };
+    let mut reader = BufReader::new(zstd::stream::Decoder::new(file).map_err(|e| corrupt(&e))?);
+    let mut header = String::new();
+    reader.read_line(&mut header).map_err(|e| corrupt(&e))?;
+
+    // the older layouts were a single JSON value written without any newlines
+    if !header.ends_with('\n') {
+        let result = match serde_json::from_str(&header).map_err(|e| corrupt(&e))? {
+            Unversioned::Options(cache) => StoredCache::Current(Box::new(CacheFile {
+                header: CacheHeader {
+                    format_version: 1,
+                    puzzle: cache.puzzle,
+                    dictionary_hash: None,
+                    options: cache.options,
+                    created: None,
+                },
+                solutions: cache.solutions,
+                words: cache.words,
+            })),
+            Unversioned::Bare(solutions) => StoredCache::Legacy(solutions),
+        };
+        return Ok(result);
+    }
+
+    let version = serde_json::from_str::<Version>(&header)
+        .map_err(|e| corrupt(&format!("unreadable header: {}", e)))?
+        .format_version;
+    if version > FORMAT_VERSION {
+        return Err(CacheError::WrongVersion { path: path.to_string(), found: version });
+    }
+    let header = serde_json::from_str::<CacheHeader>(&header)
+        .map_err(|e| corrupt(&format!("unreadable header: {}", e)))?;
+    let (solutions, words) = if version == 2 {
+        let data = serde_json::from_reader::<_, CacheData>(reader).map_err(|e| corrupt(&e))?;
+        (data.solutions, data.words)
+    } else {
+        read_binary(&mut reader).map_err(|e| corrupt(&e))?
+    };
+
+    Ok(StoredCache::Current(Box::new(CacheFile {
+        header,
+        solutions,
+        words,
+    })))
+}
+
+/// A cache read back and checked against what was asked for, ready to use
+pub struct LoadedCache {
+    pub puzzle: Puzzle,
+    pub solutions: TransformAndPossibilitiesList,
+    pub words: WordInfo,
+    /// None for the oldest caches, which are a bare list of solutions
+    pub header: Option<CacheHeader>,
+    /// How many transforms there were before blank rules the cache wasn't built with narrowed them down
+    pub before_blanks: Option<usize>,
+}
+
+/// Reads the cache at `path` and checks it was built for the puzzle and options asked for. `given` names the options
+/// that were asked for, as `SolverOptions::differences` calls them, since the rest can be whatever the cache used.
+/// Blank rules stricter than the cache's narrow it down, but looser ones let in words it never had so it's refused.
+/// The oldest caches don't record their puzzle, so for those it has to be given, and is only taken if the cache fits it
+pub fn load(path: &str, puzzle: Option<&Puzzle>, options: &SolverOptions, given: &[&str]) -> Result<LoadedCache, CacheError> {
+    let (puzzle, solutions, words, header) = match read(path)? {
+        StoredCache::Current(cache) => {
+            let cached_puzzle = cache.header.check(path, puzzle, options, given)?;
+            let cached = &cache.header.options;
+            if !options.blanks.is_empty() && solutions::blanks_looser(&cached_puzzle, &options.blanks, &cached.blanks, &cache.solutions) {
+                let differences = cached.differences(options).into_iter()
+                    .filter(|(name, _, _)| *name == "blanks")
+                    .map(|(name, cached, requested)| format!("  {}: cached {}, requested {}", name, cached, requested))
+                    .collect::<Vec<_>>();
+                return Err(CacheError::WrongPuzzle {
+                    path: path.to_string(),
+                    reason: format!("was built with blank rules that leave out letters the requested ones allow:\n{}", differences.join("\n")),
+                });
+            }
+            let cache = *cache;
+            (cached_puzzle, cache.solutions, cache.words, Some(cache.header))
+        },
+        StoredCache::Legacy(solutions) => {
+            let puzzle = puzzle.ok_or_else(|| CacheError::NoPuzzle(path.to_string()))?;
+            (puzzle.clone(), solutions, WordInfo::default(), None)
+        },
+    };
+    // everything after this sizes itself from the puzzle, so solutions of another shape can't get any further
+    check_fits(path, &puzzle, &solutions)?;
+    let cached_blanks = header.as_ref().map_or(&[][..], |header| &header.options.blanks);
+    let (solutions, before_blanks) = if !options.blanks.is_empty() && options.blanks != cached_blanks {
+        let before = solutions.len();
+        (solutions::revalidate_blanks(&puzzle, &options.blanks, solutions), Some(before))
+    } else {
+        (solutions, None)
+    };
+    Ok(LoadedCache {
+        puzzle,
+        solutions,
+        words,
+        header,
+        before_blanks,
+    })
+}
+
+/// Checks the solutions could have come from the puzzle. A transform never changes how long the words are, so every
+/// transform needs a list for each puzzle word and every word in a list the length of the puzzle word it's for
+fn check_fits(path: &str, puzzle: &Puzzle, solutions: &TransformAndPossibilitiesList) -> Result<(), CacheError> {
+    let lengths = puzzle.words().map(str::len).collect::<Vec<_>>();
+    let wrong = |reason: String| CacheError::WrongPuzzle { path: path.to_string(), reason: format!("doesn't fit the puzzle \"{}\": {}", puzzle, reason) };
+    // lists are shared between transforms, so each only needs looking at once for each length
+    let mut checked = HashSet::new();
+    for (transform, possibilities) in solutions {
+        if possibilities.len() != lengths.len() {
+            return Err(wrong(format!("it has {} words but transform {:?} has {}", lengths.len(), transform, possibilities.len())));
+        }
+        for (position, (list, length)) in possibilities.iter().zip(&lengths).enumerate() {
+            if !checked.insert((Arc::as_ptr(list), *length)) {
+                continue;
+            }
+            if let Some(word) = list.iter().find(|word| word.len() != *length) {
+                return Err(wrong(format!("word {} has {} letters but transform {:?} has \"{}\" there", position + 1, length, transform, word)));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Where a search for the cache at `path` keeps its checkpoint
+pub fn checkpoint_path(path: &str) -> String {
+    format!("{}.checkpoint", path)
+}
+
+/// Searches for every solution to the puzzle and writes them to the cache at `path`. Finished L sides are saved to
+/// the checkpoint at `checkpoint_path(path)` as they're done, so a search that was stopped carries on from there,
+/// and it's removed once the cache is written. Progress is reported through `progress`, which can also cancel it
+pub fn solve(path: &str, puzzle: &Puzzle, options: &SolverOptions, dictionary: &Dictionary, progress: &Progress) -> Result<CacheFile, SearchError> {
+    options.check(puzzle).map_err(|e| SearchError::Options(e.to_string()))?;
+    let mut checkpoint = Checkpoint::open(&checkpoint_path(path), puzzle, options, dictionary)
+        .map_err(|e| SearchError::Checkpoint(e.to_string()))?;
+    let solutions = solutions::get_all_solutions(puzzle, options, dictionary, Some(&mut checkpoint), progress)?;
+    let cache = CacheFile {
+        header: CacheHeader::new(puzzle.text(), Some(dictionary.hash().to_string()), options.clone()),
+        words: dictionary.info_for(&solutions),
+        solutions,
+    };
+    write(path, &cache).map_err(|e| SearchError::Write(e.to_string()))?;
+    // a checkpoint left behind only has L sides of this same search in it, so another run of it would still be right
+    let _ = checkpoint.remove();
+    Ok(cache)
+}
+
+/// Rewrites a cache in the current format, giving back what was read so the version it had can be seen.
+/// The oldest caches don't record their puzzle, so it has to be given, and the cache has to fit it
+pub fn convert(input: &str, output: &str, puzzle: Option<&Puzzle>) -> Result<CacheFile, Box<dyn Error>> {
+    let cache = match read(input)? {
+        StoredCache::Current(cache) => *cache,
+        StoredCache::Legacy(solutions) => {
+            let puzzle = puzzle.ok_or_else(|| CacheError::NoPuzzle(input.to_string()))?;
+            // the puzzle goes in the header and is trusted from then on, so it had better be the right one
+            check_fits(input, puzzle, &solutions)?;
+            CacheFile {
+                header: CacheHeader { format_version: 0, created: None, ..CacheHeader::new(puzzle.text(), None, SolverOptions::legacy()) },
+                solutions,
+                words: WordInfo::default(),
+            }
+        },
+    };
+    write(output, &cache)?;
+    Ok(cache)
+}
+
+/// Combines the caches written by sharded runs into one, the same as if the search had been run in one go.
+/// Every shard has to be there exactly once, and they all have to come from the same search
+pub fn merge_shards(inputs: &[String]) -> Result<CacheFile, Box<dyn Error>> {
+    let mut shards: Vec<(&String, Shard, CacheFile)> = vec![];
+    for input in inputs {
+        let StoredCache::Current(cache) = read(input).map_err(|e| format!("could not read {}: {}", input, e))? else {
+            return Err(format!("{} is not a shard file", input).into());
+        };
+        let shard = cache.header.options.shard.ok_or_else(|| format!("{} is a complete cache, not a shard", input))?;
+        shards.push((input, shard, *cache));
+    }
+    let Some((first_path, first_shard, first)) = shards.first() else {
+        return Err("no shard files given".into());
+    };
+
+    let unsharded = |options: &SolverOptions| SolverOptions { shard: None, ..options.clone() };
+    let mut found: Vec<Option<&String>> = vec![None; first_shard.count];
+    for (path, shard, cache) in &shards {
+        if cache.header.puzzle != first.header.puzzle || cache.header.dictionary_hash != first.header.dictionary_hash
+            || unsharded(&cache.header.options) != unsharded(&first.header.options) {
+            return Err(format!("{} and {} come from different searches", first_path, path).into());
+        }
+        if shard.count != first_shard.count {
+            return Err(format!("{} is shard {} but {} is shard {}", first_path, first_shard, path, shard).into());
+        }
+        // a shard file written by hand or by something else could say anything
+        if shard.index >= shard.count {
+            return Err(format!("{} says it is shard {}, which is out of range", path, shard).into());
+        }
+        if let Some(other) = found[shard.index] {
+            return Err(format!("shard {} appears twice: {} and {}", shard, other, path).into());
+        }
+        found[shard.index] = Some(path);
+    }
+    let missing = found.iter().enumerate()
+        .filter(|(_, path)| path.is_none())
+        .map(|(index, _)| (index + 1).to_string())
+        .collect::<Vec<_>>();
+    if !missing.is_empty() {
+        return Err(format!("missing shard {} of {}", missing.join(", "), first_shard.count).into());
+    }
+
+    let header = CacheHeader::new(&first.header.puzzle, first.header.dictionary_hash.clone(), unsharded(&first.header.options));
+    shards.sort_by_key(|(_, shard, _)| shard.index);
+    let mut words = WordInfo::default();
+    let mut solutions = vec![];
+    for (_, _, cache) in shards {
+        words.extend(cache.words);
+        solutions.extend(cache.solutions);
+    }
+    Ok(CacheFile {
+        header,
+        solutions,
+        words,
+    })
+}
+/// Writes the header line and then the solutions in the binary layout of `write_binary`. The file is written next to
+/// `path` and renamed over it once it's complete, so a crash part way through leaves whatever was there before
+pub fn write(path: &str, cache: &CacheFile) -> Result<(), Box<dyn Error>> {
+    let temporary = format!("{}.tmp-{}", path, std::process::id());
+    let result = write_to(&temporary, cache).and_then(|_| Ok(std::fs::rename(&temporary, path)?));
+    if result.is_err() {
+        let _ = std::fs::remove_file(&temporary);
+    }
+    result.map_err(|e| format!("could not write {}: {}", path, e).into())
+}
+
+fn write_to(path: &str, cache: &CacheFile) -> Result<(), Box<dyn Error>> {
+    let file = std::fs::File::create(path)?;
+    let mut writer = BufWriter::new(zstd::stream::Encoder::new(file, 0)?);
+
+    let header = CacheHeader { format_version: FORMAT_VERSION, ..cache.header.clone() };
+    writeln!(writer, "{}", serde_json::to_string(&header)?)?;
+    write_binary(&mut writer, cache)?;
+
+    let file = writer.into_inner().map_err(|e| e.into_error())?.finish()?;
+    // make sure it's on disk before it replaces the old one
+    file.sync_all()?;
+    Ok(())
+}
+
+// Everything after the header is little endian u32s apart from the letters, tiers and word text:
+//   the word table: count, then for each word its length, its bytes, its tier (0 for unknown) and rank (0 for unknown)
+//   the list table: count, then for each list its length and the ids of its words
+//   the transforms: count and number of positions, then for each transform its number of pairs, each pair as
+//   two letters, and the id of the list at each position
+// Lists are shared between transforms in memory and stay shared through the file, so each one is only stored once
+
+fn write_binary(writer: &mut impl Write, cache: &CacheFile) -> std::io::Result<()> {
+    let mut word_ids: HashMap<&str, u32> = HashMap::new();
+    let mut words: Vec<&str> = vec![];
+    let mut list_ids: HashMap<&[String], u32> = HashMap::new();
+    // most positions point at a list we've already seen, this saves hashing its contents again
+    let mut seen: HashMap<*const Vec<String>, u32> = HashMap::new();
+    let mut lists: Vec<&[String]> = vec![];
+    for list in cache.solutions.iter().flat_map(|(_, possibilities)| possibilities) {
+        if seen.contains_key(&Arc::as_ptr(list)) {
+            continue;
+        }
+        let id = *list_ids.entry(list.as_slice()).or_insert_with(|| {
+            lists.push(list.as_slice());
+            lists.len() as u32 - 1
+        });
+        seen.insert(Arc::as_ptr(list), id);
+        for word in list.iter() {
+            word_ids.entry(word).or_insert_with(|| {
+                words.push(word);
+                words.len() as u32 - 1
+            });
+        }
+    }
+    // words the info knows about that no list uses any more still get their tier and rank kept
+    for word in cache.words.tiers.keys().chain(cache.words.ranks.keys()) {
+        word_ids.entry(word).or_insert_with(|| {
+            words.push(word);
+            words.len() as u32 - 1
+        });
+    }
+
+    write_u32(writer, words.len())?;
+    for word in &words {
+        write_u32(writer, word.len())?;
+        writer.write_all(word.as_bytes())?;
+        let tier = match cache.words.tiers.get(*word) {
+            None => 0,
+            Some(Tier::Common) => 1,
+            Some(Tier::Full) => 2,
+            Some(Tier::Custom) => 3,
+        };
+        writer.write_all(&[tier])?;
+        write_u32(writer, cache.words.rank(word).unwrap_or(0))?;
+    }
+
+    write_u32(writer, lists.len())?;
+    for list in &lists {
+        write_u32(writer, list.len())?;
+        for word in list.iter() {
+            write_u32(writer, word_ids[word.as_str()] as usize)?;
+        }
+    }
+
+    write_u32(writer, cache.solutions.len())?;
+    write_u32(writer, cache.solutions.first().map_or(0, |(_, possibilities)| possibilities.len()))?;
+    for (transform, possibilities) in &cache.solutions {
+        writer.write_all(&[transform.len() as u8])?;
+        for (l, r) in transform {
+            writer.write_all(&[*l as u8, *r as u8])?;
+        }
+        for list in possibilities {
+            write_u32(writer, seen[&Arc::as_ptr(list)] as usize)?;
+        }
+    }
+    Ok(())
+}
+
+fn read_binary(reader: &mut impl Read) -> Result<(TransformAndPossibilitiesList, WordInfo), Box<dyn Error>> {
+    let mut info = WordInfo::default();
+    let word_count = read_u32(reader)?;
+    let mut words = Vec::with_capacity(word_count.min(1 << 20));
+    for _ in 0..word_count {
+        let length = read_u32(reader)?;
+        // a damaged length shouldn't get to allocate gigabytes
+        if length > u16::MAX as usize {
+            return Err(format!("a word is {} bytes long", length).into());
+        }
+        let mut word = vec![0; length];
+        reader.read_exact(&mut word)?;
+        let word = String::from_utf8(word).map_err(|_| "a word isn't valid UTF-8")?;
+        let tier = match read_u8(reader)? {
+            0 => None,
+            1 => Some(Tier::Common),
+            2 => Some(Tier::Full),
+            3 => Some(Tier::Custom),
+            other => return Err(format!("unknown dictionary tier {}", other).into()),
+        };
+        if let Some(tier) = tier {
+            info.tiers.insert(word.clone(), tier);
+        }
+        match read_u32(reader)? {
+            0 => {},
+            rank => { info.ranks.insert(word.clone(), rank); },
+        }
+        words.push(word);
+    }
+
+    let list_count = read_u32(reader)?;
+    let mut lists: Vec<WordList> = Vec::with_capacity(list_count.min(1 << 20));
+    for _ in 0..list_count {
+        let length = read_u32(reader)?;
+        let list = (0..length)
+            .map(|_| read_u32(reader).and_then(|id| words.get(id).cloned().ok_or_else(|| format!("word id {} is out of range", id).into())))
+            .collect::<Result<Vec<_>, Box<dyn Error>>>()?;
+        lists.push(Arc::new(list));
+    }
+
+    let transform_count = read_u32(reader)?;
+    let positions = read_u32(reader)?;
+    let mut solutions = Vec::with_capacity(transform_count.min(1 << 20));
+    for _ in 0..transform_count {
+        let mut transform = TransformHash::new();
+        for _ in 0..read_u8(reader)? {
+            let (l, r) = (read_u8(reader)? as char, read_u8(reader)? as char);
+            if !l.is_ascii_lowercase() || !r.is_ascii_lowercase() {
+                return Err(format!("transform pair {:?}={:?} isn't two letters", l, r).into());
+            }
+            transform.insert(l, r);
+        }
+        let possibilities = (0..positions)
+            .map(|_| read_u32(reader).and_then(|id| lists.get(id).cloned().ok_or_else(|| format!("list id {} is out of range", id).into())))
+            .collect::<Result<Vec<_>, Box<dyn Error>>>()?;
+        solutions.push((transform, possibilities));
+    }
+    // anything after the transforms means the counts are off somewhere
+    if reader.read(&mut [0])? != 0 {
+        return Err("there is data after the last transform".into());
+    }
+    Ok((solutions, info))
+}
+
+fn write_u32(writer: &mut impl Write, value: usize) -> std::io::Result<()> {
+    let value = u32::try_from(value).map_err(|_| std::io::Error::other(format!("{} is too big for the cache format", value)))?;
+    writer.write_all(&value.to_le_bytes())
+}
+
+fn read_u32(reader: &mut impl Read) -> Result<usize, Box<dyn Error>> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes) as usize)
+}
+
+fn read_u8(reader: &mut impl Read) -> Result<u8, Box<dyn Error>> {
+    let mut byte = [0];
+    reader.read_exact(&mut byte)?;
+    Ok(byte[0])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{list, TempPath};
+
+    fn cache(solutions: TransformAndPossibilitiesList, words: WordInfo) -> CacheFile {
+        CacheFile {
+            header: CacheHeader::new("th. c.t", None, SolverOptions::default()),
+            solutions,
+            words,
+        }
+    }
+
+    fn round_trip(cache: &CacheFile) -> (TransformAndPossibilitiesList, WordInfo) {
+        let mut bytes = vec![];
+        write_binary(&mut bytes, cache).unwrap();
+        read_binary(&mut bytes.as_slice()).unwrap()
+    }
+
+    #[test]
+    fn round_trip_keeps_lists_shared() {
+        let the = list(&["the", "thy"]);
+        let cat = list(&["cat", "cot"]);
+        let solutions = vec![
+            (TransformHash::from([('a', 'e')]), vec![the.clone(), cat.clone()]),
+            (TransformHash::from([('a', 'o'), ('e', 'i')]), vec![the.clone(), list(&["cut"])]),
+            // the same words as another list but a different Arc, which the file stores once
+            (TransformHash::from([('b', 'c')]), vec![list(&["the", "thy"]), cat.clone()]),
+        ];
+        let (read, _) = round_trip(&cache(solutions.clone(), WordInfo::default()));
+        assert_eq!(read, solutions);
+        assert!(Arc::ptr_eq(&read[0].1[0], &read[1].1[0]));
+        assert!(Arc::ptr_eq(&read[0].1[0], &read[2].1[0]));
+        assert!(Arc::ptr_eq(&read[0].1[1], &read[2].1[1]));
+        assert!(!Arc::ptr_eq(&read[0].1[1], &read[1].1[1]));
+    }
+
+    #[test]
+    fn round_trip_keeps_tiers_and_ranks() {
+        let solutions = vec![(TransformHash::from([('a', 'e')]), vec![list(&["the", "thy", "qat"])])];
+        let mut words = WordInfo::default();
+        words.tiers.insert("the".to_string(), Tier::Common);
+        words.tiers.insert("thy".to_string(), Tier::Full);
+        words.tiers.insert("qat".to_string(), Tier::Custom);
+        words.ranks.insert("the".to_string(), 1);
+        words.ranks.insert("thy".to_string(), 4000);
+        // known to the info but in no list any more
+        words.tiers.insert("old".to_string(), Tier::Full);
+        words.ranks.insert("old".to_string(), 70);
+        let (read, info) = round_trip(&cache(solutions.clone(), words.clone()));
+        assert_eq!(read, solutions);
+        assert_eq!(info.tiers, words.tiers);
+        assert_eq!(info.ranks, words.ranks);
+    }
+
+    #[test]
+    fn round_trip_empty_solutions() {
+        let (read, info) = round_trip(&cache(vec![], WordInfo::default()));
+        assert!(read.is_empty());
+        assert!(info.tiers.is_empty() && info.ranks.is_empty());
+    }
+
+    #[test]
+    fn round_trip_transform_without_pairs() {
+        // nothing substituted, and a position with no words left
+        let solutions = vec![
+            (TransformHash::new(), vec![list(&["the"]), list(&[])]),
+            (TransformHash::from([('a', 'e')]), vec![list(&["the"]), list(&["cut"])]),
+        ];
+        let (read, _) = round_trip(&cache(solutions.clone(), WordInfo::default()));
+        assert_eq!(read, solutions);
+    }
+
+    // writes a cache, lets `damage` change the data after the header and reads it back
+    fn read_damaged(name: &str, damage: impl FnOnce(&mut Vec<u8>)) -> Result<StoredCache, CacheError> {
+        let path = TempPath::new(&format!("{}.zst", name));
+        let path = path.as_str();
+        let cache = cache(vec![(TransformHash::from([('a', 'e')]), vec![list(&["the"]), list(&["cat", "cot"])])], WordInfo::default());
+        write(path, &cache).unwrap();
+        let mut bytes = zstd::decode_all(std::fs::File::open(path).unwrap()).unwrap();
+        damage(&mut bytes);
+        std::fs::write(path, zstd::encode_all(bytes.as_slice(), 0).unwrap()).unwrap();
+        read(path)
+    }
+
+    #[test]
+    fn truncated_data_is_corrupt() {
+        assert!(read_damaged("undamaged", |_| {}).is_ok());
+        let result = read_damaged("truncated", |bytes| { bytes.truncate(bytes.len() - 2); });
+        assert!(matches!(result, Err(CacheError::Corrupt { .. })));
+    }
+
+    #[test]
+    fn trailing_data_is_corrupt() {
+        let result = read_damaged("trailing", |bytes| bytes.extend([0, 0, 0, 0]));
+        assert!(matches!(result, Err(CacheError::Corrupt { reason, .. }) if reason.contains("after the last transform")));
+    }
+
+    #[test]
+    fn merge_refuses_shards_out_of_range() {
+        let paths = [(0, "first"), (5, "bad")].map(|(index, name)| {
+            let path = TempPath::new(&format!("shard-{}.zst", name));
+            let options = SolverOptions { shard: Some(Shard { index, count: 2 }), ..SolverOptions::default() };
+            let shard = CacheFile { header: CacheHeader::new("th. c.t", None, options), ..cache(vec![], WordInfo::default()) };
+            write(path.as_str(), &shard).unwrap();
+            path
+        });
+        let result = merge_shards(&paths.iter().map(|path| path.as_str().to_string()).collect::<Vec<_>>());
+        assert!(result.err().unwrap().to_string().contains("shard 6/2, which is out of range"));
+    }
+
+    #[test]
+    fn solutions_have_to_fit_the_puzzle() {
+        let solutions = vec![(TransformHash::from([('a', 'e')]), vec![list(&["the", "thy"]), list(&["cat"])])];
+        assert!(check_fits("x", &Puzzle::new("th. c.t").unwrap(), &solutions).is_ok());
+        // too few words, too many words, and the right number of words with the wrong lengths
+        for puzzle in ["th.", "th. c.t s.e", "th. ca.t", "th c.t"] {
+            let result = check_fits("x", &Puzzle::new(puzzle).unwrap(), &solutions);
+            assert!(matches!(result, Err(CacheError::WrongPuzzle { .. })), "{} fits", puzzle);
+        }
+    }
+
+    #[test]
+    fn convert_refuses_a_puzzle_the_cache_does_not_fit() {
+        let (input, output) = (TempPath::new("bare.zst"), TempPath::new("converted.zst"));
+        let solutions = vec![(TransformHash::from([('a', 'e')]), vec![list(&["the"]), list(&["cat"])])];
+        std::fs::write(input.as_str(), zstd::encode_all(serde_json::to_string(&solutions).unwrap().as_bytes(), 0).unwrap()).unwrap();
+        let result = convert(input.as_str(), output.as_str(), Some(&Puzzle::new("th. c.t s.e").unwrap()));
+        assert!(matches!(result.err().unwrap().downcast_ref(), Some(CacheError::WrongPuzzle { .. })));
+        assert!(!output.exists());
+        assert!(convert(input.as_str(), output.as_str(), Some(&Puzzle::new("th. c.t").unwrap())).is_ok());
+    }
+
+    fn solutions() -> TransformAndPossibilitiesList {
+        vec![(TransformHash::from([('a', 'e')]), vec![list(&["the"]), list(&["cat", "cot"])])]
+    }
+
+    // a file laid out the way older builds wrote them
+    fn write_raw(path: &TempPath, text: &str) {
+        std::fs::write(path.as_str(), zstd::encode_all(text.as_bytes(), 0).unwrap()).unwrap();
+    }
+
+    #[test]
+    fn missing_file_is_missing() {
+        let path = TempPath::new("never-written.zst");
+        assert!(matches!(read(path.as_str()), Err(CacheError::Missing(_))));
+        assert!(matches!(load(path.as_str(), None, &SolverOptions::default(), &[]), Err(CacheError::Missing(_))));
+    }
+
+    #[test]
+    fn newer_version_is_refused() {
+        let path = TempPath::new("version-4.zst");
+        write_raw(&path, "{\"format_version\":4,\"something\":\"new\"}\nwhatever comes next");
+        assert!(matches!(read(path.as_str()), Err(CacheError::WrongVersion { found: 4, .. })));
+    }
+
+    #[test]
+    fn other_puzzles_and_asked_for_options_are_refused() {
+        let path = TempPath::new("built.zst");
+        write(path.as_str(), &cache(solutions(), WordInfo::default())).unwrap();
+        let puzzle = Puzzle::new("th. c.t").unwrap();
+        let options = SolverOptions::default();
+        assert!(load(path.as_str(), Some(&puzzle), &options, &[]).is_ok());
+
+        let other = Puzzle::new("th. c.g").unwrap();
+        let result = load(path.as_str(), Some(&other), &options, &[]);
+        assert!(matches!(result, Err(CacheError::WrongPuzzle { reason, .. }) if reason.contains("different puzzle")));
+
+        // a different pair count only matters if it was asked for
+        let three = SolverOptions { pairs: 3, ..SolverOptions::default() };
+        assert!(load(path.as_str(), Some(&puzzle), &three, &[]).is_ok());
+        let result = load(path.as_str(), Some(&puzzle), &three, &["pairs"]);
+        assert!(matches!(result, Err(CacheError::WrongPuzzle { reason, .. }) if reason.contains("pairs: cached 5, requested 3")));
+    }
+
+    #[test]
+    fn reads_version_0_bare_lists() {
+        let path = TempPath::new("version-0.zst");
+        write_raw(&path, &serde_json::to_string(&solutions()).unwrap());
+        let Ok(StoredCache::Legacy(read)) = read(path.as_str()) else { panic!("not read as a bare list") };
+        assert_eq!(read, solutions());
+        // which only loads with a puzzle to go with it
+        assert!(matches!(load(path.as_str(), None, &SolverOptions::default(), &[]), Err(CacheError::NoPuzzle(_))));
+        let loaded = load(path.as_str(), Some(&Puzzle::new("th. c.t").unwrap()), &SolverOptions::default(), &[]).unwrap();
+        assert!(loaded.header.is_none());
+        assert_eq!(loaded.solutions, solutions());
+    }
+
+    #[test]
+    fn reads_version_1_single_json() {
+        let path = TempPath::new("version-1.zst");
+        let options = SolverOptions { pairs: 2, ..SolverOptions::default() };
+        write_raw(&path, &serde_json::json!({
+            "puzzle": "th. c.t",
+            "options": options,
+            "solutions": solutions(),
+            "tiers": { "cot": "full" },
+        }).to_string());
+        let Ok(StoredCache::Current(cache)) = read(path.as_str()) else { panic!("not read as a version 1 cache") };
+        assert_eq!(cache.header.format_version, 1);
+        assert_eq!(cache.header.puzzle, "th. c.t");
+        assert_eq!(cache.header.options, options);
+        assert_eq!(cache.solutions, solutions());
+        assert_eq!(cache.words.tier("cot"), Tier::Full);
+
+        // before the options were recorded every cache was built the legacy way
+        write_raw(&path, &serde_json::json!({ "puzzle": "th. c.t", "solutions": solutions() }).to_string());
+        let Ok(StoredCache::Current(cache)) = read(path.as_str()) else { panic!("not read as a version 1 cache") };
+        assert_eq!(cache.header.options, SolverOptions::legacy());
+    }
+
+    #[test]
+    fn reads_version_2_header_and_json() {
+        let path = TempPath::new("version-2.zst");
+        let header = CacheHeader { format_version: 2, ..CacheHeader::new("th. c.t", Some("abc".to_string()), SolverOptions::default()) };
+        write_raw(&path, &format!("{}\n{}", serde_json::to_string(&header).unwrap(), serde_json::json!({
+            "solutions": solutions(),
+            "tiers": { "the": "common" },
+            "ranks": { "the": 1 },
+        })));
+        let Ok(StoredCache::Current(cache)) = read(path.as_str()) else { panic!("not read as a version 2 cache") };
+        assert_eq!(cache.header.format_version, 2);
+        assert_eq!(cache.header.dictionary_hash.as_deref(), Some("abc"));
+        assert_eq!(cache.solutions, solutions());
+        assert_eq!(cache.words.rank("the"), Some(1));
+    }
+
+    #[test]
+    fn failed_write_leaves_the_old_file() {
+        let path = TempPath::new("kept.zst");
+        write(path.as_str(), &cache(solutions(), WordInfo::default())).unwrap();
+        let before = std::fs::read(path.as_str()).unwrap();
+        // a directory where the temporary file should go makes creating it fail
+        let temporary = format!("{}.tmp-{}", path.as_str(), std::process::id());
+        std::fs::create_dir(&temporary).unwrap();
+        let result = write(path.as_str(), &cache(vec![], WordInfo::default()));
+        std::fs::remove_dir(&temporary).unwrap();
+        assert!(result.is_err());
+        assert_eq!(std::fs::read(path.as_str()).unwrap(), before);
+    }
+}
@@ -0,0 +1,213 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::error::Error;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::sync::{Arc, Mutex};
+use serde::{Deserialize, Serialize};
+use crate::dictionary::Dictionary;
+use crate::puzzle::Puzzle;
+use crate::solutions::{SolverOptions, TransformAndPossibilitiesList, TransformHash, WordList};
+
+/// The finished L sides of a search, appended to the file as they complete so a restarted run can skip them.
+/// The file is JSON lines: a header saying which search it belongs to, then `[index, lists, transforms]` for each
+/// finished L side. Transforms share word lists, so each list is written once, in the entry that first uses it, and
+/// every transform after that gives its lists as ids counting up from the first list in the file
+pub struct Checkpoint {
+    path: String,
+    file: Mutex<Written>,
+    finished: HashSet<usize>,
+    resumed: BTreeMap<usize, TransformAndPossibilitiesList>,
+    resumed_survivors: usize,
+}
+
+// the file along with the ids of the lists already in it
+struct Written {
+    file: File,
+    // every list the file has, by address so lists shared in memory aren't hashed again
+    by_address: HashMap<usize, u32>,
+    // and by words, so a list with the same words as one already written isn't written again. Holding on to the
+    // lists also keeps their addresses from being reused
+    by_words: HashMap<WordList, u32>,
+}
+
+impl Written {
+    // the id of the list, adding it to `new` if the file doesn't have it yet
+    fn id(&mut self, list: &WordList, new: &mut Vec<WordList>) -> u32 {
+        let address = Arc::as_ptr(list) as usize;
+        if let Some(id) = self.by_address.get(&address) {
+            return *id;
+        }
+        let next = self.by_words.len() as u32;
+        let id = *self.by_words.entry(list.clone()).or_insert_with(|| {
+            new.push(list.clone());
+            next
+        });
+        self.by_address.insert(address, id);
+        id
+    }
+}
+
+#[derive(Serialize, Deserialize, PartialEq)]
+struct Header {
+    puzzle: String,
+    options: SolverOptions,
+    /// `Dictionary::hash` of the word lists being searched, since the entries are only right for those words
+    #[serde(default)]
+    dictionary_hash: Option<String>,
+}
+
+type Entry = (usize, Vec<WordList>, Vec<(TransformHash, Vec<u32>)>);
+
+impl Checkpoint {
+    /// Opens the checkpoint at `path`, loading whatever a previous run of the same search got through.
+    /// A checkpoint left by a different puzzle, different options or different dictionaries is refused rather than overwritten
+    pub fn open(path: &str, puzzle: &Puzzle, options: &SolverOptions, dictionary: &Dictionary) -> Result<Checkpoint, Box<dyn Error>> {
+        let header = Header {
+            puzzle: puzzle.text().to_string(),
+            options: options.clone(),
+            dictionary_hash: Some(dictionary.hash().to_string()),
+        };
+        let mut finished = HashSet::new();
+        let mut resumed = BTreeMap::new();
+        let mut lists: Vec<WordList> = vec![];
+
+        let mut file = OpenOptions::new().read(true).write(true).create(true).truncate(false).open(path)?;
+        let mut reader = BufReader::new(&file);
+        let mut line = String::new();
+        // the length of the file up to the last complete line, anything after that is a half-written entry
+        let mut valid_length = 0;
+
+        if reader.read_line(&mut line)? > 0 {
+            let found = serde_json::from_str::<Header>(&line).map_err(|e| format!("checkpoint {} is unreadable: {}", path, e))?;
+            if found.dictionary_hash != header.dictionary_hash && found.puzzle == header.puzzle && found.options == header.options {
+                return Err(format!("checkpoint {} was made with different dictionaries, delete it to start over", path).into());
+            }
+            if found != header {
+                return Err(format!("checkpoint {} belongs to a different search (puzzle \"{}\"), delete it to start over", path, found.puzzle).into());
+            }
+            valid_length += line.len() as u64;
+            loop {
+                line.clear();
+                if reader.read_line(&mut line)? == 0 || !line.ends_with('\n') {
+                    break;
+                }
+                let Ok((index, new, transforms)) = serde_json::from_str::<Entry>(&line) else {
+                    break;
+                };
+                lists.extend(new);
+                let Some(solutions) = transforms.into_iter()
+                    .map(|(transform, ids)| Some((transform, ids.iter().map(|id| lists.get(*id as usize).cloned()).collect::<Option<Vec<_>>>()?)))
+                    .collect::<Option<TransformAndPossibilitiesList>>() else {
+                    return Err(format!("checkpoint {} is unreadable: a list id is out of range, delete it to start over", path).into());
+                };
+                finished.insert(index);
+                resumed.insert(index, solutions);
+                valid_length += line.len() as u64;
+            }
+            file.set_len(valid_length)?;
+            std::io::Seek::seek(&mut file, std::io::SeekFrom::End(0))?;
+        } else {
+            writeln!(file, "{}", serde_json::to_string(&header)?)?;
+        }
+
+        let mut written = Written {
+            file,
+            by_address: HashMap::new(),
+            by_words: HashMap::new(),
+        };
+        for list in &lists {
+            written.id(list, &mut vec![]);
+        }
+        Ok(Checkpoint {
+            path: path.to_string(),
+            file: Mutex::new(written),
+            finished,
+            resumed_survivors: resumed.values().map(Vec::len).sum(),
+            resumed,
+        })
+    }
+
+    pub fn is_finished(&self, index: usize) -> bool {
+        self.finished.contains(&index)
+    }
+
+    pub fn finished_count(&self) -> usize {
+        self.finished.len()
+    }
+
+    /// How many transforms the loaded L sides found
+    pub fn resumed_survivors(&self) -> usize {
+        self.resumed_survivors
+    }
+
+    /// Hands over the solutions loaded from the file, keyed by the index of their L side
+    pub fn take_resumed(&mut self) -> BTreeMap<usize, TransformAndPossibilitiesList> {
+        std::mem::take(&mut self.resumed)
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// Appends a finished L side to the file
+    pub fn record(&self, index: usize, solutions: &TransformAndPossibilitiesList) -> std::io::Result<()> {
+        let mut written = self.file.lock().unwrap();
+        // ids are handed out under the lock so they follow the order of the lines in the file
+        let mut new = vec![];
+        let transforms = solutions.iter()
+            .map(|(transform, lists)| (transform, lists.iter().map(|list| written.id(list, &mut new)).collect::<Vec<_>>()))
+            .collect::<Vec<_>>();
+        let line = serde_json::to_string(&(index, new, transforms))?;
+        writeln!(written.file, "{}", line)?;
+        written.file.flush()
+    }
+
+    /// Deletes the checkpoint once the full results are safely written elsewhere
+    pub fn remove(self) -> std::io::Result<()> {
+        drop(self.file);
+        std::fs::remove_file(&self.path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{dictionary_file, list, TempPath};
+
+    fn dictionary(path: &TempPath, words: &str) -> Dictionary {
+        Dictionary::load(&[dictionary_file(path, "full", words)]).unwrap()
+    }
+
+    #[test]
+    fn resumes_with_lists_shared_and_refuses_other_dictionaries() {
+        let words = TempPath::new("checkpoint-words.txt");
+        let path = TempPath::new("resumed.checkpoint");
+        let path = path.as_str();
+        let puzzle = Puzzle::new("th. c.t").unwrap();
+        let options = SolverOptions::default();
+
+        let the = list(&["the", "thy"]);
+        let first = vec![
+            (TransformHash::from([('a', 'o')]), vec![the.clone(), list(&["cot"])]),
+            (TransformHash::from([('a', 'u')]), vec![the.clone(), list(&["cut"])]),
+        ];
+        // the same words as the first L side's list, but not the same Arc
+        let second = vec![(TransformHash::from([('b', 'e')]), vec![list(&["the", "thy"]), list(&["cat"])])];
+        let checkpoint = Checkpoint::open(path, &puzzle, &options, &dictionary(&words, "the\nthy\ncat\ncot\ncut\n")).unwrap();
+        checkpoint.record(0, &first).unwrap();
+        checkpoint.record(3, &second).unwrap();
+        drop(checkpoint);
+
+        let mut resumed = Checkpoint::open(path, &puzzle, &options, &dictionary(&words, "the\nthy\ncat\ncot\ncut\n")).unwrap();
+        assert!(resumed.is_finished(0) && resumed.is_finished(3) && !resumed.is_finished(1));
+        assert_eq!(resumed.resumed_survivors(), 3);
+        let solutions = resumed.take_resumed();
+        assert_eq!(solutions[&0], first);
+        assert_eq!(solutions[&3], second);
+        assert!(Arc::ptr_eq(&solutions[&0][0].1[0], &solutions[&3][0].1[0]));
+        drop(resumed);
+
+        let error = Checkpoint::open(path, &puzzle, &options, &dictionary(&words, "the\nthy\ncat\n")).err().unwrap();
+        assert!(error.to_string().contains("different dictionaries"));
+    }
+}
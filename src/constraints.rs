@@ -0,0 +1,378 @@
+use std::collections::{BTreeSet, HashMap};
+use crate::labels::Labels;
+use crate::puzzle::Puzzle;
+use crate::solutions::{TransformHash, WordList};
+
+/// A rule about whole sentences that can't be written as separate word lists per position.
+/// `Words` keeps these alongside its state, prunes the lists with them where it can and checks
+/// them against whole sentences when counting
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Constraint {
+    /// Every letter on the left of the transform turns up somewhere in the blanks
+    LeftLettersInBlanks,
+    /// Between min and max of this letter across all the blanks
+    LetterBudget { letter: char, min: usize, max: usize },
+    /// No word used twice in one sentence
+    DistinctWords,
+    /// Two blanks, each given as (word, blank within that word), hold the same letter
+    SharedBlank { first: (usize, usize), second: (usize, usize) },
+    /// The puzzle's labelled blanks agree across words, added by `Words` itself when the puzzle has any
+    LabelledBlanks(Labels),
+}
+
+/// Where the blanks are in each word of the puzzle, labelled or not
+pub struct Blanks(Vec<Vec<usize>>);
+
+impl Blanks {
+    pub fn new(puzzle: &Puzzle) -> Blanks {
+        Blanks(puzzle.words()
+            .map(|word| word.char_indices().filter(|(_, c)| *c == '.' || c.is_ascii_digit()).map(|(i, _)| i).collect())
+            .collect())
+    }
+
+    /// The letters a word puts in the blanks of the given position
+    pub fn letters<'a>(&'a self, position: usize, word: &'a str) -> impl Iterator<Item = char> + 'a {
+        self.0[position].iter().filter_map(|i| word.as_bytes().get(*i).map(|b| *b as char))
+    }
+
+    /// The index within its word of a (word, blank) pair, if there is such a blank
+    pub fn index(&self, (position, blank): (usize, usize)) -> Option<usize> {
+        self.0.get(position)?.get(blank).copied()
+    }
+}
+
+/// Which words to keep in one position's list, worked out by propagating a constraint
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Prune {
+    /// Words with between min and max of the letter in their blanks
+    LetterCount { letter: char, min: usize, max: usize },
+    /// Words with one of these letters at this index
+    LetterAt { index: usize, letters: BTreeSet<char> },
+    /// Words with the same letter at both indices
+    SameLetters { first: usize, second: usize },
+    /// Words whose blanks hold one of these sets of the letters, given as bits in the order of the letters
+    Covering { letters: Vec<char>, masks: BTreeSet<u32> },
+    Without(String),
+}
+
+impl Prune {
+    pub fn keeps(&self, word: &str, position: usize, blanks: &Blanks) -> bool {
+        let at = |index: usize| word.as_bytes().get(index).map(|b| *b as char);
+        match self {
+            Prune::LetterCount { letter, min, max } => {
+                let count = blanks.letters(position, word).filter(|c| c == letter).count();
+                *min <= count && count <= *max
+            },
+            Prune::LetterAt { index, letters } => at(*index).is_some_and(|c| letters.contains(&c)),
+            Prune::SameLetters { first, second } => at(*first) == at(*second),
+            Prune::Covering { letters, masks } => masks.contains(&cover_mask(letters, position, word, blanks)),
+            Prune::Without(without) => word != without,
+        }
+    }
+}
+
+impl Constraint {
+    /// Parses a letter budget like "e:1-2", or "e:1" for exactly one
+    pub fn parse_budget(text: &str) -> Result<Constraint, String> {
+        let error = || format!("could not read letter budget \"{}\", expected something like e:1-2", text);
+        let (letter, range) = text.split_once(':').ok_or_else(error)?;
+        let mut letters = letter.trim().chars();
+        let (Some(letter), None) = (letters.next(), letters.next()) else {
+            return Err(error());
+        };
+        let (min, max) = range.split_once('-').unwrap_or((range, range));
+        let min = min.trim().parse().map_err(|_| error())?;
+        let max = max.trim().parse().map_err(|_| error())?;
+        if !letter.is_ascii_lowercase() || min > max {
+            return Err(error());
+        }
+        Ok(Constraint::LetterBudget { letter, min, max })
+    }
+
+    /// Parses a pair of blanks like "1.2=3.1", the second blank of the first word and the first blank of the third
+    pub fn parse_shared_blank(text: &str, blanks: &Blanks) -> Result<Constraint, String> {
+        let error = || format!("could not read shared blanks \"{}\", expected something like 1.2=3.1", text);
+        let blank = |text: &str| -> Result<(usize, usize), String> {
+            let (word, blank) = text.trim().split_once('.').ok_or_else(error)?;
+            let word: usize = word.parse().map_err(|_| error())?;
+            let blank: usize = blank.parse().map_err(|_| error())?;
+            let pair = (word.wrapping_sub(1), blank.wrapping_sub(1));
+            blanks.index(pair).ok_or_else(|| format!("word {} has no blank {}", word, blank))?;
+            Ok(pair)
+        };
+        let (first, second) = text.split_once('=').ok_or_else(error)?;
+        Ok(Constraint::SharedBlank { first: blank(first)?, second: blank(second)? })
+    }
+
+    /// Whether pruning the lists is enough to make every sentence they can form pass, so counting needn't check it
+    pub fn enforced_by_lists(&self) -> bool {
+        match self {
+            Constraint::SharedBlank { first, second } => first.0 == second.0,
+            _ => false,
+        }
+    }
+
+    /// Counts the sentences that pass exactly, for constraints that can do it without going through them all
+    pub fn count(&self, transform: &TransformHash, sentence: &[WordList], blanks: &Blanks) -> Option<u64> {
+        match self {
+            Constraint::LeftLettersInBlanks => {
+                let letters = transform.keys().copied().collect::<Vec<_>>();
+                // how many ways the positions so far can cover each set of the letters
+                let mut counts = HashMap::from([(0u32, 1u64)]);
+                for (position, list) in sentence.iter().enumerate() {
+                    let mut words: HashMap<u32, u64> = HashMap::new();
+                    list.iter().for_each(|word| *words.entry(cover_mask(&letters, position, word, blanks)).or_default() += 1);
+                    let mut next: HashMap<u32, u64> = HashMap::new();
+                    for (covered, count) in &counts {
+                        for (mask, words) in &words {
+                            *next.entry(covered | mask).or_default() += count * words;
+                        }
+                    }
+                    counts = next;
+                }
+                Some(counts.get(&full_mask(&letters)).copied().unwrap_or(0))
+            },
+            _ => None,
+        }
+    }
+
+    pub fn check(&self, transform: &TransformHash, sentence: &[&str], blanks: &Blanks) -> bool {
+        match self {
+            Constraint::LeftLettersInBlanks => {
+                let used = sentence.iter().enumerate()
+                    .flat_map(|(position, word)| blanks.letters(position, word))
+                    .collect::<BTreeSet<_>>();
+                transform.keys().all(|letter| used.contains(letter))
+            },
+            Constraint::LetterBudget { letter, min, max } => {
+                let count = sentence.iter().enumerate()
+                    .flat_map(|(position, word)| blanks.letters(position, word))
+                    .filter(|c| c == letter)
+                    .count();
+                *min <= count && count <= *max
+            },
+            Constraint::DistinctWords => {
+                sentence.iter().enumerate().all(|(i, word)| !sentence[..i].contains(word))
+            },
+            Constraint::SharedBlank { first, second } => {
+                let letter = |(position, blank): (usize, usize)| {
+                    blanks.index((position, blank)).and_then(|index| sentence[position].as_bytes().get(index))
+                };
+                letter(*first) == letter(*second)
+            },
+            Constraint::LabelledBlanks(labels) => labels.check(sentence),
+        }
+    }
+
+    /// Works out which words can't be part of any passing sentence given the other positions' lists.
+    /// This only ever removes words that can't pass, but may leave some that don't
+    pub fn prune(&self, transform: &TransformHash, sentence: &[WordList], blanks: &Blanks) -> Vec<(usize, Prune)> {
+        match self {
+            Constraint::LeftLettersInBlanks => {
+                let letters = transform.keys().copied().collect::<Vec<_>>();
+                let full = full_mask(&letters);
+                let masks = sentence.iter().enumerate()
+                    .map(|(position, list)| list.iter().map(|word| cover_mask(&letters, position, word, blanks)).collect::<BTreeSet<_>>())
+                    .collect::<Vec<_>>();
+                let combine = |a: &BTreeSet<u32>, b: &BTreeSet<u32>| {
+                    a.iter().flat_map(|a| b.iter().map(move |b| a | b)).collect::<BTreeSet<_>>()
+                };
+                // which letters the positions before each one can have covered, and which the positions after it can
+                let mut before = vec![BTreeSet::from([0])];
+                for position_masks in &masks {
+                    before.push(combine(before.last().unwrap(), position_masks));
+                }
+                let mut after = vec![BTreeSet::from([0])];
+                for position_masks in masks.iter().rev() {
+                    after.push(combine(position_masks, after.last().unwrap()));
+                }
+                after.reverse();
+                masks.iter().enumerate()
+                    .filter_map(|(position, position_masks)| {
+                        let completable = position_masks.iter()
+                            .filter(|mask| before[position].iter().any(|a| after[position + 1].iter().any(|b| a | *mask | b == full)))
+                            .copied()
+                            .collect::<BTreeSet<_>>();
+                        (completable != *position_masks)
+                            .then(|| (position, Prune::Covering { letters: letters.clone(), masks: completable }))
+                    })
+                    .collect()
+            },
+            Constraint::LetterBudget { letter, min, max } => {
+                // the fewest and most of the letter each position can put in the blanks
+                let ranges = sentence.iter().enumerate()
+                    .map(|(position, list)| {
+                        let counts = list.iter().map(|word| blanks.letters(position, word).filter(|c| c == letter).count());
+                        (counts.clone().min().unwrap_or(0), counts.max().unwrap_or(0))
+                    })
+                    .collect::<Vec<_>>();
+                let lowest = ranges.iter().map(|(low, _)| low).sum::<usize>();
+                let highest = ranges.iter().map(|(_, high)| high).sum::<usize>();
+                ranges.iter().enumerate()
+                    .filter_map(|(position, (low, high))| {
+                        // what this position can have given the others at their extremes
+                        let allowed_min = min.saturating_sub(highest - high);
+                        let Some(allowed_max) = max.checked_sub(lowest - low) else {
+                            // nothing works, so keep nothing
+                            return Some((position, Prune::LetterCount { letter: *letter, min: 1, max: 0 }));
+                        };
+                        (allowed_min > *low || allowed_max < *high)
+                            .then_some((position, Prune::LetterCount { letter: *letter, min: allowed_min, max: allowed_max }))
+                    })
+                    .collect()
+            },
+            Constraint::DistinctWords => {
+                // a word that's the only choice somewhere can't be used anywhere else
+                sentence.iter().enumerate()
+                    .filter(|(_, list)| list.len() == 1)
+                    .flat_map(|(only, list)| {
+                        sentence.iter().enumerate()
+                            .filter(move |(position, other)| *position != only && other.contains(&list[0]))
+                            .map(|(position, _)| (position, Prune::Without(list[0].clone())))
+                    })
+                    .collect()
+            },
+            Constraint::SharedBlank { first, second } => {
+                let (Some(first_index), Some(second_index)) = (blanks.index(*first), blanks.index(*second)) else {
+                    return vec![];
+                };
+                if first.0 == second.0 {
+                    let prune = Prune::SameLetters { first: first_index, second: second_index };
+                    return if sentence[first.0].iter().all(|word| prune.keeps(word, first.0, blanks)) {
+                        vec![]
+                    } else {
+                        vec![(first.0, prune)]
+                    };
+                }
+                let letters = |position: usize, index: usize| {
+                    sentence[position].iter().filter_map(|word| word.as_bytes().get(index).map(|b| *b as char)).collect::<BTreeSet<_>>()
+                };
+                let first_letters = letters(first.0, first_index);
+                let second_letters = letters(second.0, second_index);
+                let shared = first_letters.intersection(&second_letters).copied().collect::<BTreeSet<_>>();
+                let mut prunes = vec![];
+                if shared != first_letters {
+                    prunes.push((first.0, Prune::LetterAt { index: first_index, letters: shared.clone() }));
+                }
+                if shared != second_letters {
+                    prunes.push((second.0, Prune::LetterAt { index: second_index, letters: shared }));
+                }
+                prunes
+            },
+            Constraint::LabelledBlanks(labels) => match labels.restrictions(sentence) {
+                Some(restrictions) => restrictions.into_iter()
+                    .enumerate()
+                    .flat_map(|(position, restriction)| restriction.into_iter().map(move |(index, letters)| {
+                        (position, Prune::LetterAt { index, letters: letters.letters().collect() })
+                    }))
+                    .collect(),
+                // nothing can work, which an empty set of letters says by keeping no words
+                None => vec![(0, Prune::LetterAt { index: 0, letters: BTreeSet::new() })],
+            },
+        }
+    }
+}
+
+impl std::fmt::Display for Constraint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Constraint::LeftLettersInBlanks => write!(f, "L letters in blanks"),
+            Constraint::LetterBudget { letter, min, max } => write!(f, "{} {}-{} times in blanks", letter, min, max),
+            Constraint::DistinctWords => write!(f, "no repeated words"),
+            Constraint::SharedBlank { first, second } => write!(f, "blank {}.{} = {}.{}", first.0 + 1, first.1 + 1, second.0 + 1, second.1 + 1),
+            Constraint::LabelledBlanks(_) => write!(f, "labelled blanks"),
+        }
+    }
+}
+
+/// Which of the letters (as bits, in the order given) a word puts in the blanks of the given position
+fn cover_mask(letters: &[char], position: usize, word: &str, blanks: &Blanks) -> u32 {
+    blanks.letters(position, word)
+        .filter_map(|letter| letters.iter().position(|c| *c == letter))
+        .fold(0, |mask, bit| mask | 1 << bit)
+}
+
+fn full_mask(letters: &[char]) -> u32 {
+    ((1u64 << letters.len()) - 1) as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::list;
+    use std::sync::Arc;
+
+    /// Every sentence the lists can make
+    fn sentences(lists: &[WordList]) -> Vec<Vec<&str>> {
+        lists.iter().fold(vec![vec![]], |sentences, list| {
+            sentences.iter()
+                .flat_map(|sentence| list.iter().map(move |word| [sentence.as_slice(), &[word.as_str()]].concat()))
+                .collect()
+        })
+    }
+
+    #[test]
+    fn left_letters_count_matches_checking_every_sentence() {
+        let puzzle = Puzzle::new("t.e c.t s.. .o.").unwrap();
+        let blanks = Blanks::new(&puzzle);
+        let lists = [
+            list(&["the", "tae", "tie"]),
+            list(&["cat", "cot", "cut", "cit"]),
+            list(&["sat", "sit", "set", "sea", "sad"]),
+            list(&["dog", "hot", "tot", "cod", "hoe"]),
+        ];
+        let transform: TransformHash = [('a', 'o'), ('h', 'e'), ('i', 'u')].into();
+        let constraint = Constraint::LeftLettersInBlanks;
+        let passing = sentences(&lists).iter().filter(|sentence| constraint.check(&transform, sentence, &blanks)).count();
+        assert!(passing > 0);
+        assert_eq!(constraint.count(&transform, &lists, &blanks), Some(passing as u64));
+
+        // no left letters means every sentence passes
+        assert_eq!(constraint.count(&TransformHash::new(), &lists, &blanks), Some(3 * 4 * 5 * 5));
+    }
+
+    #[test]
+    fn left_letters_prune_keeps_exactly_the_words_of_passing_sentences() {
+        let puzzle = Puzzle::new("t.e c.t s.. .o.").unwrap();
+        let blanks = Blanks::new(&puzzle);
+        let mut lists = vec![
+            list(&["the", "tae", "tie", "toe"]),
+            list(&["cat", "cot", "cut"]),
+            list(&["sat", "set", "sea"]),
+            list(&["dog", "hot", "tot", "cod"]),
+        ];
+        // only tie puts an i in the blanks and only hot an h, so the first and last lists come down to one word
+        let transform: TransformHash = [('h', 'e'), ('i', 'u')].into();
+        let constraint = Constraint::LeftLettersInBlanks;
+        let passing = sentences(&lists).into_iter()
+            .filter(|sentence| constraint.check(&transform, sentence, &blanks))
+            .map(|sentence| sentence.into_iter().map(String::from).collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+        assert_eq!(passing.len(), 9);
+
+        for (position, prune) in constraint.prune(&transform, &lists, &blanks) {
+            lists[position] = Arc::new(lists[position].iter().filter(|word| prune.keeps(word, position, &blanks)).cloned().collect());
+        }
+        for (position, list) in lists.iter().enumerate() {
+            let mut wanted = passing.iter().map(|sentence| sentence[position].clone()).collect::<Vec<_>>();
+            wanted.sort();
+            wanted.dedup();
+            let mut kept = list.to_vec();
+            kept.sort();
+            assert_eq!(kept, wanted, "position {position}");
+        }
+    }
+
+    #[test]
+    fn left_letters_prune_empties_impossible_transforms() {
+        let puzzle = Puzzle::new("t.e c.t").unwrap();
+        let blanks = Blanks::new(&puzzle);
+        let lists = [list(&["the", "tie"]), list(&["cat", "cut"])];
+        // nothing puts a z in the blanks
+        let transform: TransformHash = [('z', 'q')].into();
+        let prunes = Constraint::LeftLettersInBlanks.prune(&transform, &lists, &blanks);
+        assert!(!prunes.is_empty());
+        assert!(prunes.iter().all(|(position, prune)| lists[*position].iter().all(|word| !prune.keeps(word, *position, &blanks))));
+        assert_eq!(Constraint::LeftLettersInBlanks.count(&transform, &lists, &blanks), Some(0));
+    }
+}
@@ -0,0 +1,225 @@
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use serde::{Deserialize, Serialize};
+use crate::solutions::TransformAndPossibilitiesList;
+
+/// How obscure a dictionary's words are, so the TUI can hide the less likely ones
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Tier {
+    Common,
+    Full,
+    Custom,
+}
+
+impl std::str::FromStr for Tier {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Tier, String> {
+        match s {
+            "common" => Ok(Tier::Common),
+            "full" => Ok(Tier::Full),
+            "custom" => Ok(Tier::Custom),
+            _ => Err(format!("unknown dictionary tier \"{}\", expected common, full or custom", s)),
+        }
+    }
+}
+
+impl std::fmt::Display for Tier {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Tier::Common => write!(f, "common"),
+            Tier::Full => write!(f, "full"),
+            Tier::Custom => write!(f, "custom"),
+        }
+    }
+}
+
+/// A newline separated word list and the tier its words belong to
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DictionarySpec {
+    pub tier: Tier,
+    pub path: String,
+}
+
+impl DictionarySpec {
+    /// Parses "tier:path", e.g. "full:./node_modules/word-list/words.txt"
+    pub fn parse(text: &str) -> Result<DictionarySpec, Box<dyn Error>> {
+        let (tier, path) = text.split_once(':')
+            .ok_or_else(|| format!("could not read dictionary \"{}\", expected something like full:words.txt", text))?;
+        Ok(DictionarySpec {
+            tier: tier.parse()?,
+            path: path.to_string(),
+        })
+    }
+
+    /// The word list that shipped with the original solver
+    pub fn default_list() -> Vec<DictionarySpec> {
+        vec![DictionarySpec {
+            tier: Tier::Common,
+            path: "./node_modules/word-list/commons.txt".to_string(),
+        }]
+    }
+}
+
+/// The tier of every word that appears in a set of solutions
+pub type WordTiers = BTreeMap<String, Tier>;
+
+/// Where each word sits in the layered dictionary, starting from 1. commons.txt is sorted by frequency,
+/// so a lower rank is a more common word
+pub type WordRanks = BTreeMap<String, usize>;
+
+/// What the dictionaries said about the words in a set of solutions, stored alongside them
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct WordInfo {
+    #[serde(default)]
+    pub tiers: WordTiers,
+    #[serde(default)]
+    pub ranks: WordRanks,
+}
+
+impl WordInfo {
+    /// Caches from before tiers were recorded only used the common list
+    pub fn tier(&self, word: &str) -> Tier {
+        self.tiers.get(word).copied().unwrap_or(Tier::Common)
+    }
+
+    /// None for words from caches that predate ranks
+    pub fn rank(&self, word: &str) -> Option<usize> {
+        self.ranks.get(word).copied()
+    }
+
+    pub fn extend(&mut self, other: WordInfo) {
+        self.tiers.extend(other.tiers);
+        self.ranks.extend(other.ranks);
+    }
+}
+
+/// Every dictionary in use layered into one word list. A word listed by more than one dictionary
+/// belongs to whichever was given first, and keeps its position there
+pub struct Dictionary {
+    words: Vec<String>,
+    hash: String,
+    // the tier and rank of each word
+    entries: HashMap<String, (Tier, usize)>,
+}
+
+impl Dictionary {
+    pub fn load(specs: &[DictionarySpec]) -> Result<Dictionary, Box<dyn Error>> {
+        let mut words = vec![];
+        let mut entries = HashMap::new();
+        let mut hash = ContentHash::new();
+        for spec in specs {
+            let file_content = std::fs::read_to_string(&spec.path)
+                .map_err(|e| format!("could not read dictionary {}: {}", spec.path, e))?;
+            hash.add(spec.tier, &file_content);
+            for word in file_content.lines().map(str::trim).filter(|word| !word.is_empty()) {
+                if !entries.contains_key(word) {
+                    words.push(word.to_string());
+                    entries.insert(word.to_string(), (spec.tier, words.len()));
+                }
+            }
+        }
+        Ok(Dictionary { words, hash: hash.finish(), entries })
+    }
+
+    /// A hash of the tiers and contents of the word lists, so a cache can tell whether they changed since it was built
+    pub fn hash(&self) -> &str {
+        &self.hash
+    }
+
+    /// The hash `load` would give, without building the dictionary
+    pub fn hash_files(specs: &[DictionarySpec]) -> Result<String, Box<dyn Error>> {
+        let mut hash = ContentHash::new();
+        for spec in specs {
+            let file_content = std::fs::read_to_string(&spec.path)
+                .map_err(|e| format!("could not read dictionary {}: {}", spec.path, e))?;
+            hash.add(spec.tier, &file_content);
+        }
+        Ok(hash.finish())
+    }
+
+    pub fn words(&self) -> &[String] {
+        &self.words
+    }
+
+    /// The tiers and ranks of just the words the solutions use, which is what gets stored alongside them
+    pub fn info_for(&self, solutions: &TransformAndPossibilitiesList) -> WordInfo {
+        let mut info = WordInfo::default();
+        // most lists are shared between transforms, so only look through each one once
+        let mut seen = std::collections::HashSet::new();
+        for (_, possibilities) in solutions {
+            for word in possibilities.iter().filter(|words| seen.insert(std::sync::Arc::as_ptr(words))).flat_map(|words| words.iter()) {
+                if !info.tiers.contains_key(word) && let Some((tier, rank)) = self.entries.get(word) {
+                    info.tiers.insert(word.clone(), *tier);
+                    info.ranks.insert(word.clone(), *rank);
+                }
+            }
+        }
+        info
+    }
+}
+
+// 64 bit FNV-1a, which unlike the std hashers is the same on every build
+struct ContentHash(u64);
+
+impl ContentHash {
+    fn new() -> ContentHash {
+        ContentHash(0xcbf29ce484222325)
+    }
+
+    fn add(&mut self, tier: Tier, content: &str) {
+        // the tier is part of it since it changes what the TUI shows, and the separator keeps files from running together
+        for byte in tier.to_string().bytes().chain([0]).chain(content.bytes()).chain([0]) {
+            self.0 = (self.0 ^ byte as u64).wrapping_mul(0x100000001b3);
+        }
+    }
+
+    fn finish(self) -> String {
+        format!("{:016x}", self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::solutions::TransformHash;
+    use crate::test_support::{dictionary_file, lists, TempPath};
+
+    #[test]
+    fn first_dictionary_wins_and_ranks_carry_on() {
+        let (common, full) = (TempPath::new("layer-common.txt"), TempPath::new("layer-full.txt"));
+        let specs = [
+            dictionary_file(&common, "common", "the\n\ncat\n  \n"),
+            dictionary_file(&full, "full", "cot\ncat\nthe\ncut\n"),
+        ];
+        let dictionary = Dictionary::load(&specs).unwrap();
+        // blank lines aren't words and words already seen aren't added again
+        assert_eq!(dictionary.words(), ["the", "cat", "cot", "cut"]);
+
+        let solutions = vec![(TransformHash::new(), lists(&[&["the"], &["cat", "cot", "cut", "cab"]]))];
+        let info = dictionary.info_for(&solutions);
+        assert_eq!(info.tier("cat"), Tier::Common);
+        assert_eq!(info.rank("cat"), Some(2));
+        assert_eq!(info.tier("cot"), Tier::Full);
+        assert_eq!(info.rank("cot"), Some(3));
+        assert_eq!(info.rank("cut"), Some(4));
+        // words no dictionary has aren't stored at all
+        assert!(!info.tiers.contains_key("cab"));
+        assert_eq!(info.rank("cab"), None);
+    }
+
+    #[test]
+    fn hash_follows_contents_and_tiers() {
+        let (first, second) = (TempPath::new("hash-first.txt"), TempPath::new("hash-second.txt"));
+        let specs = [dictionary_file(&first, "common", "the\ncat\n"), dictionary_file(&second, "full", "cot\n")];
+        let hash = Dictionary::hash_files(&specs).unwrap();
+        assert_eq!(Dictionary::load(&specs).unwrap().hash(), hash);
+
+        let retiered = [specs[0].clone(), DictionarySpec { tier: Tier::Custom, ..specs[1].clone() }];
+        assert_ne!(Dictionary::hash_files(&retiered).unwrap(), hash);
+        // moving a word from one file to the other is a change too
+        let moved = [dictionary_file(&first, "common", "the\n"), dictionary_file(&second, "full", "cat\ncot\n")];
+        assert_ne!(Dictionary::hash_files(&moved).unwrap(), hash);
+    }
+}
@@ -17,6 +17,12 @@ impl<T: Eq + Hash + Clone> Histogram<T> {
     }
 }
 
+impl<T: Eq + Hash + Clone> Default for Histogram<T> {
+    fn default() -> Histogram<T> {
+        Histogram::new()
+    }
+}
+
 impl<T: Eq + std::hash::Hash + Clone> Histogram<T> {
 
     pub fn push_multiple(&mut self, s: &T, increment: u64) {
@@ -24,7 +30,7 @@ impl<T: Eq + std::hash::Hash + Clone> Histogram<T> {
     }
 
     pub fn most_popular(&self) -> Vec<(T, u64)> {
-        let mut result = self.data.iter().map(|(s,n)| (s.clone(),n.clone())).collect::<Vec<_>>();
+        let mut result = self.data.iter().map(|(s,n)| (s.clone(),*n)).collect::<Vec<_>>();
         result.sort_by_key(|(_,count)| u64::MAX-(*count));
         result
     }
@@ -1,7 +1,8 @@
 use core::time;
+use std::sync::{mpsc, Arc};
 use std::vec;
 use color_eyre::Result;
-use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind};
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
 use ratatui::{
     buffer::Buffer,
     layout::{Constraint, Layout, Rect},
@@ -11,13 +12,17 @@ use ratatui::{
     widgets::{Block, Paragraph, Widget},
     DefaultTerminal, Frame,
 };
-use crate::solutions::{self, TransformAndPossibilitiesList};
-use super::words::Words;
-
-pub fn run(data: solutions::TransformAndPossibilitiesList) {
+use jipher::constraints;
+use jipher::dictionary::{Tier, WordInfo};
+use jipher::language_model::{LanguageModel, RankedSentence, SentenceRanker};
+use jipher::puzzle::Puzzle;
+use jipher::solutions::TransformAndPossibilitiesList;
+use jipher::words::{WordOrder, Words};
+
+pub fn run(puzzle: Puzzle, data: TransformAndPossibilitiesList, info: WordInfo, language_model: Option<LanguageModel>, beam_width: usize) {
     color_eyre::install().unwrap();
     let terminal = &mut ratatui::init();
-    let result = App::new(data).run(terminal);
+    let result = App::new(puzzle, data, info, language_model, beam_width).run(terminal);
     ratatui::restore();
     result.unwrap();
 }
@@ -29,26 +34,152 @@ struct App {
     input_buffer: Vec<char>,
     words: Words,
     tab: usize,
+    visible_tiers: &'static [Tier],
+    order: WordOrder,
+    max_rank: Option<usize>,
+    language_model: Option<Arc<LanguageModel>>,
+    beam_width: usize,
+    // keeps each transform's search between rankings, and is off on the ranking thread while one runs
+    ranker: Option<SentenceRanker>,
+    ranking: Option<mpsc::Receiver<(SentenceRanker, Vec<RankedSentence>)>>,
+    // another ranking was asked for while one was running
+    rank_again: bool,
+    best_sentences: Vec<RankedSentence>,
+    // how many edits had been made when the sentences shown were ranked, and when the running ranking started
+    ranked_at: Option<usize>,
+    ranking_at: usize,
+    // what went wrong with the last rule, shown in the rules view
+    rule_error: String,
 }
 
+/// Rules that read their details from the input, so number keys type rather than switch views while one is selected
+const RULES_WITH_INPUT: [usize; 2] = [4, 5];
+
+/// How many sentences the Best Sentences view ranks
+const BEST_SENTENCES: usize = 200;
+
+/// The dictionary tiers shown in the word lists, cycled through with <Ctrl-T>
+const TIER_FILTERS: [&[Tier]; 3] = [
+    &[Tier::Common, Tier::Full, Tier::Custom],
+    &[Tier::Common, Tier::Custom],
+    &[Tier::Common],
+];
+
+/// The most obscure rank shown in the word lists, cycled through with <Ctrl-F>
+const RANK_LIMITS: [Option<usize>; 4] = [None, Some(5000), Some(2000), Some(1000)];
+
 enum Modes {
     Home,
     WordEliminator,
     SentenceBrowser,
     RuleApply,
     Save,
+    BestSentences,
     Quit,
 }
 
 impl App {
 
-    fn new(data: TransformAndPossibilitiesList) -> App {
+    fn new(puzzle: Puzzle, data: TransformAndPossibilitiesList, info: WordInfo, language_model: Option<LanguageModel>, beam_width: usize) -> App {
         App {
             mode: Modes::Home,
             scroll_level: 0,
             input_buffer: vec![],
-            words:     Words::new(data),
+            words:     Words::new(puzzle, data, info),
             tab: 0,
+            visible_tiers: TIER_FILTERS[0],
+            order: WordOrder::Combinations,
+            max_rank: RANK_LIMITS[0],
+            ranker: language_model.is_some().then(|| SentenceRanker::new(beam_width)),
+            language_model: language_model.map(Arc::new),
+            beam_width,
+            ranking: None,
+            rank_again: false,
+            best_sentences: vec![],
+            ranked_at: None,
+            ranking_at: 0,
+            rule_error: String::new(),
+        }
+    }
+
+    /// Ranks what's left on another thread, only searching the transforms edited since the last ranking.
+    /// If a ranking is already running, another one starts when it's done
+    fn rank_sentences(&mut self) {
+        let Some(language_model) = &self.language_model else {
+            return;
+        };
+        let Some(mut ranker) = self.ranker.take() else {
+            self.rank_again |= self.ranking_at != self.words.edits();
+            return;
+        };
+        let language_model = language_model.clone();
+        let state = self.words.state().clone();
+        let accept = self.words.checker();
+        let (sender, receiver) = mpsc::channel();
+        std::thread::spawn(move || {
+            ranker.update(&language_model, &state);
+            let best = ranker.best(&state, BEST_SENTENCES, &accept);
+            // nobody is listening if the app has quit
+            let _ = sender.send((ranker, best));
+        });
+        self.ranking = Some(receiver);
+        self.ranking_at = self.words.edits();
+    }
+
+    /// Picks up the sentences once the ranking thread is done, returning whether it was
+    fn check_ranking(&mut self) -> bool {
+        let Some(result) = self.ranking.as_ref().map(|receiver| receiver.try_recv()) else {
+            return false;
+        };
+        match result {
+            Ok((ranker, best)) => {
+                self.ranker = Some(ranker);
+                self.best_sentences = best;
+                self.ranked_at = Some(self.ranking_at);
+            },
+            Err(mpsc::TryRecvError::Empty) => return false,
+            // the thread died, so its searches are gone with it
+            Err(mpsc::TryRecvError::Disconnected) => self.ranker = Some(SentenceRanker::new(self.beam_width)),
+        }
+        self.ranking = None;
+        if std::mem::take(&mut self.rank_again) {
+            self.rank_sentences();
+        }
+        true
+    }
+
+    fn cycle_tiers(&mut self) {
+        let current = TIER_FILTERS.iter().position(|filter| *filter == self.visible_tiers).unwrap_or(0);
+        self.visible_tiers = TIER_FILTERS[(current + 1) % TIER_FILTERS.len()];
+        self.scroll_level = 0;
+    }
+
+    fn toggle_order(&mut self) {
+        self.order = match self.order {
+            WordOrder::Combinations => WordOrder::Commonness,
+            WordOrder::Commonness => WordOrder::Combinations,
+        };
+        self.scroll_level = 0;
+    }
+
+    fn cycle_rank_limit(&mut self) {
+        let current = RANK_LIMITS.iter().position(|limit| *limit == self.max_rank).unwrap_or(0);
+        self.max_rank = RANK_LIMITS[(current + 1) % RANK_LIMITS.len()];
+        self.scroll_level = 0;
+    }
+
+    fn is_visible(&self, word: &str) -> bool {
+        // words without a rank come from caches that predate ranks, so there's nothing to judge them by
+        self.visible_tiers.contains(&self.words.tier(word))
+            && self.max_rank.is_none_or(|max| self.words.rank(word).is_none_or(|rank| rank <= max))
+    }
+
+    /// Marks words that aren't from the common tier in the word lists, along with how common they are
+    fn tier_tag(&self, word: &str) -> String {
+        let rank = self.words.rank(word).map_or(String::new(), |rank| format!("#{}", rank));
+        match self.words.tier(word) {
+            Tier::Common => rank,
+            tier => format!("{} [{}]", rank, tier),
         }
     }
 
@@ -58,7 +189,8 @@ impl App {
         terminal.clear()?;
         while !matches!(self.mode,Modes::Quit) {
             terminal.draw(|frame| self.draw(frame))?;
-            self.handle_events()?;
+            // redraw after a key, or when a ranking comes in
+            while !self.handle_events()? && !self.check_ranking() {}
         }
         Ok(())
     }
@@ -67,7 +199,12 @@ impl App {
         frame.render_widget(self, frame.area());
     }
 
-    fn handle_events(&mut self) -> Result<()> {
+    /// Handles the next event, returning whether there was one. While ranking this gives up after a moment
+    /// so the ranking can be checked on
+    fn handle_events(&mut self) -> Result<bool> {
+        if self.ranking.is_some() && !event::poll(time::Duration::from_millis(200))? {
+            return Ok(false);
+        }
         match event::read()? {
             // it's important to check that the event is a key press event as
             // crossterm also emits key release and repeat events on Windows.
@@ -76,36 +213,63 @@ impl App {
             }
             _ => {}
         };
-        Ok(())
+        Ok(true)
     }
 
     fn scroll(&mut self, x: isize) {
         self.scroll_level = self.scroll_level.saturating_add_signed(x);
     }
 
+    fn typing_rule_input(&self) -> bool {
+        matches!(self.mode, Modes::RuleApply) && RULES_WITH_INPUT.contains(&self.scroll_level)
+    }
+
+    fn input_changed(&mut self) {
+        // in the rules view the scroll level is which rule is selected, which typing shouldn't change
+        if !matches!(self.mode, Modes::RuleApply) {
+            self.scroll_level = 0;
+        }
+    }
+
+    fn combinations_display(&self) -> String {
+        let estimate = if self.words.combinations_estimated() { "~" } else { "" };
+        format!("{}{}", estimate, self.words.total_combinations())
+    }
+
     fn handle_key_event(&mut self, key_event: KeyEvent) {
         match key_event.code {
             //KeyCode::Char('q') => self.mode = Modes::Quit,
             KeyCode::Esc => self.mode = Modes::Quit,
+            KeyCode::Char(c) if c.is_ascii_digit() && self.typing_rule_input() => self.input_buffer.push(c),
             KeyCode::Char('1') => self.change_mode(Modes::Home),
             KeyCode::Char('2') => self.change_mode(Modes::WordEliminator),
             KeyCode::Char('3') => self.change_mode(Modes::SentenceBrowser),
             KeyCode::Char('4') => self.change_mode(Modes::RuleApply),
             KeyCode::Char('5') => self.change_mode(Modes::Save),
+            KeyCode::Char('6') => self.change_mode(Modes::BestSentences),
             KeyCode::Char(' ') => {
                     match self.mode {
                         Modes::SentenceBrowser => {
                             let to_require = self.input_buffer.iter().collect();
                             self.words.require_word_positional(to_require, self.tab);
                         },
+                        Modes::BestSentences => {
+                            let to_require = self.input_buffer.iter().collect();
+                            self.words.require_word(to_require);
+                            self.rank_sentences();
+                        }
                         _ => {
                             let to_require = self.input_buffer.iter().collect();
                             self.words.require_word(to_require);
                         }
                     }
                 },
-            KeyCode::Char(c) => {self.input_buffer.push(c); self.scroll_level = 0},
-            KeyCode::Backspace => {self.input_buffer.pop(); self.scroll_level = 0},
+            KeyCode::Char('t') if key_event.modifiers.contains(KeyModifiers::CONTROL) => self.cycle_tiers(),
+            KeyCode::Char('o') if key_event.modifiers.contains(KeyModifiers::CONTROL) => self.toggle_order(),
+            KeyCode::Char('f') if key_event.modifiers.contains(KeyModifiers::CONTROL) => self.cycle_rank_limit(),
+            KeyCode::Char('r') if key_event.modifiers.contains(KeyModifiers::CONTROL) && matches!(self.mode, Modes::BestSentences) => self.rank_sentences(),
+            KeyCode::Char(c) => {self.input_buffer.push(c); self.input_changed()},
+            KeyCode::Backspace => {self.input_buffer.pop(); self.input_changed()},
             KeyCode::Enter => {
                     match self.mode {
                         Modes::RuleApply => self.apply_rules(self.scroll_level),
@@ -113,6 +277,11 @@ impl App {
                             let to_remove = self.input_buffer.iter().collect();
                             self.words.remove_words_positional(vec![to_remove],self.tab);
                         }
+                        Modes::BestSentences => {
+                            let to_remove = self.input_buffer.iter().collect();
+                            self.words.remove_words(vec![to_remove]);
+                            self.rank_sentences();
+                        }
                         _ => 
                             {
                                 let to_remove = self.input_buffer.iter().collect();
@@ -149,11 +318,8 @@ impl App {
     fn apply_rules(&mut self, index: usize) {
         match index {
             0 => {
-                //gather complete word lists for each position (can be cribbed from positional hists)
-                //for each word in a given position
-                    //check blank spaces
-                //okay this rapidly becomes extremely complicated (infeasible? because each word will develop dependencies on other words. very difficult to calculate much less present)
-                todo!()
+                //every letter the transform takes away has to turn up again in the blanks
+                self.words.add_constraint(constraints::Constraint::LeftLettersInBlanks);
             },
             1 => {
                 //gets rid of two letter words that no one uses
@@ -188,7 +354,24 @@ impl App {
                     "ciao",
                 ].into_iter().map(|word| word.to_string()).collect());
             }
-            _ => return,
+            3 => self.words.add_constraint(constraints::Constraint::DistinctWords),
+            4 | 5 => {
+                let input = self.input_buffer.iter().collect::<String>();
+                let constraint = if index == 4 {
+                    constraints::Constraint::parse_budget(&input)
+                } else {
+                    constraints::Constraint::parse_shared_blank(&input, self.words.blanks())
+                };
+                match constraint {
+                    Ok(constraint) => {
+                        self.rule_error.clear();
+                        self.input_buffer.clear();
+                        self.words.add_constraint(constraint);
+                    },
+                    Err(e) => self.rule_error = e,
+                }
+            },
+            _ => (),
         }
     }
 }
@@ -200,9 +383,12 @@ impl Widget for &App {
         //top bar: Lists words, transforms remaining
         let title = Line::from(vec![
             Span::from(" The Eliminator ").bold(),
-            Span::from(format!("[Combinations: {} | Transforms: {}]",
-                self.words.total_combinations(),
-                self.words.total_transforms())
+            Span::from(format!("[Combinations: {} | Transforms: {} | Words: {} up to rank {}, by {} (^T ^F ^O)]",
+                self.combinations_display(),
+                self.words.total_transforms(),
+                self.visible_tiers.iter().map(|tier| tier.to_string()).collect::<Vec<_>>().join("+"),
+                self.max_rank.map_or("any".to_string(), |max| max.to_string()),
+                self.order)
             )
         ]);
 
@@ -214,8 +400,8 @@ impl Widget for &App {
             Line::from("Possibility List Loaded"),
             Line::from(format!("Total Words: {}",self.words.total_words())),
             Line::from(format!("Total Possible Transforms: {}", self.words.total_transforms())),
-            Line::from(format!("Total Possible Combinations: {}", self.words.total_combinations())),
-            Line::from(format!("A random solution is below, press <Tab> to generate more:")),
+            Line::from(format!("Total Possible Combinations: {}", self.combinations_display())),
+            Line::from("A random solution is below, press <Tab> to generate more:"),
             Line::from(random_transform).centered(),
             Line::from(random_words).centered(),
         ]);
@@ -243,7 +429,10 @@ impl Widget for &App {
                 Line::from(self.input_buffer_display()).centered().underlined(),
                 Line::from(vec![
                     match current_word_mode {
-                        WordMode::Popular => Span::from("Most Combinations").bold(),
+                        WordMode::Popular => Span::from(match self.order {
+                            WordOrder::Combinations => "Most Combinations",
+                            WordOrder::Commonness => "Most Common",
+                        }).bold(),
                         WordMode::Critical => Span::from("Critical Words").bold(),
                     },
                     Span::from(" (Tab to switch)")
@@ -252,24 +441,24 @@ impl Widget for &App {
 
             match current_word_mode {
                 WordMode::Popular => {
-                    let selected_words = self.words.get_top(self.scroll_level..(self.scroll_level + 80),
+                    let selected_words = self.words.get_top(self.scroll_level..(self.scroll_level + 80), self.order,
                     |(word,_count)| {
-                                self.input_buffer.len() < 1 || word.starts_with(&self.input_buffer.iter().collect::<String>())
+                                self.is_visible(word) && (self.input_buffer.is_empty() || word.starts_with(&self.input_buffer.iter().collect::<String>()))
                     });
 
                     selected_words.into_iter()
                         .map(|(word, count)| 
-                            Line::from(format!("  {: <8}: {: >18}",word,count))
+                            Line::from(format!("  {: <8}: {: >18} {}",word,count,self.tier_tag(&word)))
                         )
                         .for_each(|line| lines.push(line));
                 },
                 WordMode::Critical => {
-                    self.words.critical_words()
+                    self.words.critical_words(self.order)
                         .into_iter()
-                        .filter(|(word, _count)| word.starts_with(&self.input_buffer.iter().collect::<String>()))
+                        .filter(|(word, _count)| self.is_visible(word) && word.starts_with(&self.input_buffer.iter().collect::<String>()))
                         .skip(self.scroll_level)
                         .for_each(|(word,count)| lines.push(
-                            Line::from(format!("  {: <8}: {: >18}",word,count))
+                            Line::from(format!("  {: <8}: {: >18} {}",word,count,self.tier_tag(&word)))
                         ));
                 }
 
@@ -310,14 +499,32 @@ impl Widget for &App {
 
             lines.push(create_line( 2, "Eliminate several unlikely words (lucy, nsw, toyota) that greatly reduce state-space"));
 
+            lines.push(create_line(3, "No word used twice in a sentence"));
+
+            // these two read their details from the input, e.g. e:1-2 or 1.2=3.1
+            lines.push(create_line(4, "Letter budget: the blanks hold a letter between min and max times (type e.g. e:1-2)"));
+
+            lines.push(create_line(5, "Two blanks hold the same letter, as word.blank (type e.g. 1.2=3.1)"));
+
+            lines.push(Line::from(""));
+            lines.push(Line::from(self.input_buffer_display()).centered().underlined());
+            if !self.rule_error.is_empty() {
+                lines.push(Line::from(self.rule_error.clone()).red());
+            }
+            if !self.words.constraints().is_empty() {
+                lines.push(Line::from(""));
+                lines.push(Line::from(format!("Applied: {}",
+                    self.words.constraints().iter().map(|constraint| constraint.to_string()).collect::<Vec<_>>().join(", "))));
+            }
+
             Text::from(lines)
         };
 
             //sentence browser
         let sentence_browser = {
-            let puzzle = "t. .i.d t..rt. .o .o. t... na. ..ne y.. .w.lm. ..cy d.ne".to_string();
+            let puzzle = self.words.puzzle().text().to_string();
             
-            let words = puzzle.split(" ")
+            let words = self.words.puzzle().words()
                 .map(|word| " ".repeat(word.len()))
                 .collect::<Vec<_>>();
             let words2 = words.clone();
@@ -337,18 +544,29 @@ impl Widget for &App {
                 ])
                 .collect::<Vec<Span>>();
 
-            let mut popular_words = (self.scroll_level..self.scroll_level+40).into_iter()
+            // sort and filter each position once rather than once per line
+            let columns = self.words.positional_histograms().iter()
+                .enumerate()
+                .map(|(word_index, word_hist)| {
+                    self.words.sorted(word_hist, self.order)
+                        .into_iter()
+                        .filter(|(word,_)| {
+                            self.is_visible(word) && (word_index != self.tab || word.starts_with(&self.input_buffer.iter().collect::<String>()))
+                        })
+                        .skip(self.scroll_level)
+                        .take(40)
+                        .map(|(word,_)| word)
+                        .collect::<Vec<_>>()
+                })
+                .collect::<Vec<_>>();
+
+            let mut popular_words = (0..40)
                 .map(|line_index| {
                     Line::from(
-                        self.words.positional_histograms().into_iter()
+                        columns.iter()
                         .enumerate()
-                        .map(|(word_index, word_hist)| {
-                            if let Some((word,_)) = word_hist.most_popular()
-                                .iter()
-                                .filter(|(word,_)| {
-                                    word_index != self.tab || word.starts_with(&self.input_buffer.iter().collect::<String>())
-                                })
-                                .nth(line_index)
+                        .map(|(word_index, column)| {
+                            if let Some(word) = column.get(line_index)
                                 {
                                     word.clone() + " "
                                 } else {
@@ -386,6 +604,39 @@ impl Widget for &App {
         };
             
 
+            //best sentences
+        let best_sentences = {
+            let mut lines = vec![
+                Line::from("Most plausible sentences").centered().bold(),
+                Line::from(vec![
+                    Span::from("Type a word and hit "),
+                    Span::from("<Enter>").blue(),
+                    Span::from(" to delete it or "),
+                    Span::from("<Space>").blue(),
+                    Span::from(" to require it and rerank."),
+                ]).italic().centered(),
+                Line::from(self.input_buffer_display()).centered().underlined(),
+            ];
+
+            if self.language_model.is_none() {
+                lines.push(Line::from("No language model loaded, start with --lm NGRAMS_PATH to rank sentences"));
+            } else if self.ranking.is_some() {
+                lines.push(Line::from("Ranking sentences..."));
+            } else if self.ranked_at.is_none() {
+                lines.push(Line::from(vec![Span::from("<Ctrl-R>").blue(), Span::from(" to rank sentences")]));
+            } else if self.ranked_at != Some(self.words.edits()) {
+                lines.push(Line::from(vec![Span::from("Words have changed since this ranking, "), Span::from("<Ctrl-R>").blue(), Span::from(" to rerank")]));
+            }
+            self.best_sentences.iter()
+                .enumerate()
+                .skip(self.scroll_level)
+                .for_each(|(rank, sentence)| lines.push(
+                    Line::from(format!("{: >4}. {: <40} {: >8.2}  {:?}", rank + 1, sentence.words.join(" "), sentence.score, sentence.transform))
+                ));
+
+            Text::from(lines)
+        };
+
         //bottom bar: lists modes and indicates current mode
         let bottom_bar = Line::from(vec![
             "|".into(),
@@ -399,6 +650,8 @@ impl Widget for &App {
             "<4> |".blue().bold(),
             if matches!(self.mode,Modes::Save) {" Save ".on_light_magenta()} else {" Save ".into()},
             "<5> |".blue().bold(),
+            if matches!(self.mode,Modes::BestSentences) {" Best Sentences ".on_light_magenta()} else {" Best Sentences ".into()},
+            "<6> |".blue().bold(),
             if matches!(self.mode,Modes::Quit) {" Quit ".on_light_magenta()} else {" Quit ".into()},
             "<Esc> ".blue().bold(),
             "|".into(),
@@ -416,6 +669,7 @@ impl Widget for &App {
                 Modes::WordEliminator => word_browser,
                 Modes::RuleApply => rules_apply,
                 Modes::SentenceBrowser => sentence_browser,
+                Modes::BestSentences => best_sentences,
                 _ => Text::from("Not yet implemented..."),
             }
         )
@@ -0,0 +1,158 @@
+use std::collections::BTreeMap;
+use crate::alphabet::Alphabet;
+use crate::puzzle::Puzzle;
+use crate::solutions::WordList;
+
+/// The labelled blanks of a puzzle. Each digit is one unknown letter wherever it appears, even across words,
+/// and different digits are different letters
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Labels {
+    // the (word, index within the word) of every blank with each label
+    occurrences: BTreeMap<char, Vec<(usize, usize)>>,
+}
+
+impl Labels {
+    pub fn new(puzzle: &Puzzle) -> Labels {
+        let mut occurrences: BTreeMap<char, Vec<(usize, usize)>> = BTreeMap::new();
+        for (position, word) in puzzle.words().enumerate() {
+            for (index, c) in word.char_indices().filter(|(_, c)| c.is_ascii_digit()) {
+                occurrences.entry(c).or_default().push((position, index));
+            }
+        }
+        Labels { occurrences }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.occurrences.is_empty()
+    }
+
+    /// Whether a whole sentence gives each label one letter, and different labels different letters
+    pub fn check(&self, sentence: &[&str]) -> bool {
+        let mut used = Alphabet::of([]);
+        for occurrences in self.occurrences.values() {
+            let mut letters = occurrences.iter()
+                .map(|(position, index)| sentence.get(*position).and_then(|word| word.as_bytes().get(*index)).map(|b| *b as char));
+            let Some(Some(letter)) = letters.next() else {
+                return false;
+            };
+            if used.contains(letter) || !letters.all(|other| other == Some(letter)) {
+                return false;
+            }
+            used = used.union(Alphabet::of([letter]));
+        }
+        true
+    }
+
+    /// Works out which letters each label can still have given the lists, and from that which letters each
+    /// position's labelled blanks should be narrowed to, as (index within the word, letters) for each position.
+    /// Nothing to narrow means the lists are as tight as this can make them; None means no sentence can work.
+    /// Narrowing one list can make others narrower, so keep going until there's nothing to narrow
+    pub fn restrictions(&self, sentence: &[WordList]) -> Option<Vec<Vec<(usize, Alphabet)>>> {
+        let present = |(position, index): (usize, usize)| {
+            Alphabet::of(sentence[position].iter().filter_map(|word| word.as_bytes().get(index).map(|b| *b as char)))
+        };
+        // a label can only be a letter that every one of its blanks can have
+        let mut allowed = self.occurrences.iter()
+            .map(|(label, occurrences)| {
+                (*label, occurrences.iter().fold(Alphabet::ALL, |alphabet, occurrence| alphabet.intersect(present(*occurrence))))
+            })
+            .collect::<BTreeMap<_, _>>();
+        // a label down to one letter takes that letter away from the others
+        let mut changed = true;
+        while changed {
+            changed = false;
+            let settled = allowed.iter().filter(|(_, letters)| letters.len() == 1).map(|(label, letters)| (*label, *letters)).collect::<Vec<_>>();
+            for (label, letter) in settled {
+                for (_, letters) in allowed.iter_mut().filter(|(other, _)| **other != label) {
+                    if !letters.intersect(letter).is_empty() {
+                        *letters = letters.without(letter);
+                        changed = true;
+                    }
+                    if letters.is_empty() {
+                        return None;
+                    }
+                }
+            }
+        }
+        if allowed.values().any(Alphabet::is_empty) {
+            return None;
+        }
+
+        let mut restrictions = vec![vec![]; sentence.len()];
+        for (label, occurrences) in &self.occurrences {
+            for &(position, index) in occurrences {
+                if !present((position, index)).is_subset(allowed[label]) {
+                    restrictions[position].push((index, allowed[label]));
+                }
+            }
+        }
+        Some(restrictions)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::lists;
+
+    fn labels(puzzle: &str) -> Labels {
+        Labels::new(&Puzzle::new(puzzle).unwrap())
+    }
+
+    #[test]
+    fn same_label_converges_across_words() {
+        let labels = labels("1. .1");
+        let sentence = lists(&[&["ab", "cd", "ef"], &["xa", "yc", "zq"]]);
+        // 1 is the first letter of one word and the second of the other, so it can only be a or c
+        let a_or_c = Alphabet::of(['a', 'c']);
+        assert_eq!(labels.restrictions(&sentence), Some(vec![vec![(0, a_or_c)], vec![(1, a_or_c)]]));
+        assert!(labels.check(&["ab", "xa"]));
+        assert!(labels.check(&["cd", "yc"]));
+        assert!(!labels.check(&["ab", "yc"]));
+        assert!(!labels.check(&["ef", "zq"]));
+    }
+
+    #[test]
+    fn nothing_to_narrow_when_lists_agree() {
+        let labels = labels("1. .1");
+        let sentence = lists(&[&["ab", "cd"], &["xa", "yc"]]);
+        assert_eq!(labels.restrictions(&sentence), Some(vec![vec![], vec![]]));
+    }
+
+    #[test]
+    fn distinct_labels_stay_apart() {
+        let labels = labels("1. 2.");
+        assert!(labels.check(&["ab", "bx"]));
+        assert!(!labels.check(&["ab", "ax"]));
+
+        // 1 can only be a, which takes a away from 2
+        let sentence = lists(&[&["ab"], &["ax", "bx", "cx"]]);
+        assert_eq!(labels.restrictions(&sentence), Some(vec![vec![], vec![(0, Alphabet::of(['b', 'c']))]]));
+    }
+
+    #[test]
+    fn singletons_eliminate_in_a_chain() {
+        // 1 is a, so 2 has to be b, so 3 has to be c
+        let labels = labels("1 2 3");
+        let sentence = lists(&[&["a"], &["a", "b"], &["a", "b", "c"]]);
+        assert_eq!(labels.restrictions(&sentence), Some(vec![
+            vec![],
+            vec![(0, Alphabet::of(['b']))],
+            vec![(0, Alphabet::of(['c']))],
+        ]));
+    }
+
+    #[test]
+    fn impossible_labels_give_none() {
+        assert_eq!(labels("1 2").restrictions(&lists(&[&["a"], &["a"]])), None);
+        assert_eq!(labels("1. .1").restrictions(&lists(&[&["ab"], &["xc"]])), None);
+        // three labels can't share two letters, which narrowing doesn't see but checking sentences does
+        let three = labels("1 2 3");
+        assert_eq!(three.restrictions(&lists(&[&["a", "b"], &["a", "b"], &["a", "b"]])), Some(vec![vec![]; 3]));
+        for first in ["a", "b"] {
+            for second in ["a", "b"] {
+                assert!(["a", "b"].iter().all(|third| !three.check(&[first, second, third])));
+            }
+        }
+    }
+}
@@ -0,0 +1,268 @@
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use rayon::prelude::*;
+use std::sync::Arc;
+use crate::solutions::{Possibilities, TransformAndPossibilitiesList, TransformHash, WordList};
+
+/// How far the beam search looks by default: the number of partial sentences kept per transform at each word
+pub const DEFAULT_BEAM_WIDTH: usize = 20;
+
+// stupid backoff: each time we have to fall back to a shorter history the score is multiplied by 0.4
+const BACKOFF: f64 = -0.39794;
+const SENTENCE_START: &str = "<s>";
+
+/// Unigram, bigram and trigram counts for judging how plausible a sentence is.
+/// The file has one n-gram per line, its words followed by how often it occurs, e.g. "of the 23135851".
+/// Unigrams are optional, without them a word's count is how often it ends a bigram. "<s>" marks the start of a sentence
+pub struct LanguageModel {
+    // keyed by the n-gram's words joined with spaces
+    counts: HashMap<String, u64>,
+    // how often each n-gram starts a longer one, for turning counts into probabilities
+    histories: HashMap<String, u64>,
+    unigram_total: u64,
+}
+
+/// A complete sentence from the beam search and the transform it came from
+#[derive(Clone, Debug)]
+pub struct RankedSentence {
+    pub words: Vec<String>,
+    /// log10 of the sentence's probability, so closer to 0 is more plausible
+    pub score: f64,
+    pub transform: TransformHash,
+}
+
+impl LanguageModel {
+    pub fn load(path: &str) -> Result<LanguageModel, Box<dyn Error>> {
+        let file_content = std::fs::read_to_string(path)
+            .map_err(|e| format!("could not read language model {}: {}", path, e))?;
+        LanguageModel::parse(&file_content).map_err(|e| format!("{} {}", path, e).into())
+    }
+
+    /// Reads n-gram counts, a line each of the words followed by how often they come up
+    pub fn parse(file_content: &str) -> Result<LanguageModel, Box<dyn Error>> {
+        let mut counts = HashMap::new();
+        let mut histories: HashMap<String, u64> = HashMap::new();
+        let mut bigram_ends: HashMap<String, u64> = HashMap::new();
+        let mut has_unigrams = false;
+
+        for (number, line) in file_content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut fields = line.split_whitespace().collect::<Vec<_>>();
+            let count = fields.pop()
+                .and_then(|count| count.parse::<u64>().ok())
+                .filter(|_| !fields.is_empty())
+                .ok_or_else(|| format!("line {}: expected words followed by a count, got \"{}\"", number + 1, line))?;
+            // only trigrams and below are used
+            if fields.len() > 3 {
+                continue;
+            }
+            let words = fields.iter().map(|word| word.to_lowercase()).collect::<Vec<_>>();
+            match words.len() {
+                1 => has_unigrams = true,
+                2 => *bigram_ends.entry(words[1].clone()).or_default() += count,
+                _ => {},
+            }
+            if words.len() > 1 {
+                *histories.entry(words[..words.len() - 1].join(" ")).or_default() += count;
+            }
+            *counts.entry(words.join(" ")).or_default() += count;
+        }
+
+        if !has_unigrams {
+            counts.extend(bigram_ends);
+        }
+        let unigram_total = counts.iter()
+            .filter(|(ngram, _)| !ngram.contains(' '))
+            .map(|(_, count)| count)
+            .sum::<u64>()
+            .max(1);
+
+        Ok(LanguageModel { counts, histories, unigram_total })
+    }
+
+    /// The histories to try for a word, longest first, each with how often it starts a longer n-gram
+    fn contexts(&self, history: &[&str]) -> Vec<(String, Option<u64>)> {
+        (0..history.len())
+            .map(|start| {
+                let context = history[start..].join(" ");
+                let total = self.histories.get(&context).copied();
+                (context, total)
+            })
+            .collect()
+    }
+
+    /// log10 P(word | history), backing off to shorter histories when the longer one hasn't been seen.
+    /// `key` is just somewhere to build n-grams without allocating for every word
+    fn log_probability(&self, contexts: &[(String, Option<u64>)], word: &str, key: &mut String) -> f64 {
+        let mut penalty = 0.0;
+        for (context, total) in contexts {
+            if let Some(total) = total {
+                key.clear();
+                key.push_str(context);
+                key.push(' ');
+                key.push_str(word);
+                if let Some(count) = self.counts.get(key.as_str()) {
+                    return penalty + (*count as f64 / *total as f64).log10();
+                }
+            }
+            penalty += BACKOFF;
+        }
+        match self.counts.get(word) {
+            Some(count) => penalty + (*count as f64 / self.unigram_total as f64).log10(),
+            // words the model has never seen are rarer than anything it has
+            None => penalty + (0.5 / self.unigram_total as f64).log10(),
+        }
+    }
+
+    /// The most plausible sentences across every transform that `accept` lets through. Each transform is searched
+    /// with a beam of the given width, and a sentence that more than one transform can make is only listed once
+    pub fn best_sentences(&self, solutions: &TransformAndPossibilitiesList, count: usize, beam_width: usize,
+        accept: &(dyn Fn(&TransformHash, &[&str]) -> bool + Sync)) -> Vec<RankedSentence> {
+        let mut ranker = SentenceRanker::new(beam_width);
+        ranker.update(self, solutions);
+        ranker.best(solutions, count, accept)
+    }
+
+    /// The complete sentences a beam search through the lists ends up with, as the index of each word in its list
+    fn beam_search(&self, possibilities: &[WordList], beam_width: usize) -> Vec<Sentence> {
+        let mut beam: Vec<Sentence> = vec![(vec![], 0.0)];
+        for word_list in possibilities {
+            // score every extension first and only build the ones that make the cut
+            let mut extensions = beam.iter().enumerate()
+                .flat_map(|(index, (words, score))| {
+                    let history = std::iter::once(SENTENCE_START)
+                        .chain(words.iter().enumerate().map(|(p, i)| possibilities[p][*i as usize].as_str()))
+                        .collect::<Vec<_>>();
+                    let contexts = self.contexts(&history[history.len().saturating_sub(2)..]);
+                    let mut key = String::new();
+                    word_list.iter().enumerate().map(move |(i, word)| (index, i as u32, score + self.log_probability(&contexts, word, &mut key)))
+                })
+                .collect::<Vec<_>>();
+            extensions.sort_by(|a, b| b.2.total_cmp(&a.2));
+            extensions.truncate(beam_width);
+            beam = extensions.into_iter()
+                .map(|(index, word, score)| {
+                    let mut words = beam[index].0.clone();
+                    words.push(word);
+                    (words, score)
+                })
+                .collect();
+        }
+        beam.retain(|(words, _)| words.len() == possibilities.len());
+        beam
+    }
+}
+
+/// The beam search results of every transform, kept between rankings so that after an edit only the
+/// transforms whose lists changed have to be searched again
+pub struct SentenceRanker {
+    beam_width: usize,
+    // the lists each transform was searched with, and the sentences found as indices into them
+    searched: HashMap<TransformHash, (Possibilities, Vec<Sentence>)>,
+}
+
+// a sentence as the index of each word in its position's list, and its score
+type Sentence = (Vec<u32>, f64);
+
+impl SentenceRanker {
+    pub fn new(beam_width: usize) -> SentenceRanker {
+        SentenceRanker { beam_width, searched: HashMap::new() }
+    }
+
+    /// Searches the transforms that are new or have had their lists edited since the last update, and forgets
+    /// the ones that are gone
+    pub fn update(&mut self, model: &LanguageModel, solutions: &TransformAndPossibilitiesList) {
+        let current = solutions.iter().map(|(transform, _)| transform).collect::<HashSet<_>>();
+        self.searched.retain(|transform, _| current.contains(transform));
+        // edits replace a list rather than change it, so an unchanged transform still has the very same lists
+        let unchanged = |transform: &TransformHash, possibilities: &Possibilities| {
+            self.searched.get(transform).is_some_and(|(searched, _)| {
+                searched.len() == possibilities.len() && searched.iter().zip(possibilities).all(|(a, b)| Arc::ptr_eq(a, b))
+            })
+        };
+        let searched = solutions.par_iter()
+            .filter(|(transform, possibilities)| !unchanged(transform, possibilities))
+            .map(|(transform, possibilities)| {
+                (transform.clone(), (possibilities.clone(), model.beam_search(possibilities, self.beam_width)))
+            })
+            .collect::<Vec<_>>();
+        self.searched.extend(searched);
+    }
+
+    /// The most plausible sentences from the last update that `accept` lets through, with a sentence that more
+    /// than one transform can make only listed once. Transforms the update didn't see are left out
+    pub fn best(&self, solutions: &TransformAndPossibilitiesList, count: usize,
+        accept: &(dyn Fn(&TransformHash, &[&str]) -> bool + Sync)) -> Vec<RankedSentence> {
+        let mut candidates = solutions.par_iter()
+            .filter_map(|(transform, _)| self.searched.get_key_value(transform))
+            .flat_map_iter(|(transform, (possibilities, sentences))| {
+                sentences.iter().map(move |(words, score)| {
+                    let words = words.iter().enumerate().map(|(p, i)| possibilities[p][*i as usize].as_str()).collect::<Vec<_>>();
+                    (transform, words, *score)
+                })
+            })
+            .filter(|(transform, words, _)| accept(transform, words))
+            .collect::<Vec<_>>();
+        // stable, so ties keep the order of the transforms
+        candidates.sort_by(|a, b| b.2.total_cmp(&a.2));
+
+        let mut seen = HashSet::new();
+        candidates.into_iter()
+            .filter(|(_, words, _)| seen.insert(words.clone()))
+            .take(count)
+            .map(|(transform, words, score)| RankedSentence {
+                words: words.into_iter().map(str::to_string).collect(),
+                score,
+                transform: transform.clone(),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::list;
+
+    fn model() -> LanguageModel {
+        LanguageModel::parse("the 100\ncat 10\ncot 5\nsat 8\n<s> the 50\nthe cat 9\nthe cot 2\ncat sat 7\ncot sat 1\n").unwrap()
+    }
+
+    fn sentences(ranked: &[RankedSentence]) -> Vec<String> {
+        ranked.iter().map(|sentence| sentence.words.join(" ")).collect()
+    }
+
+    #[test]
+    fn ranks_by_the_model() {
+        let solutions = vec![(TransformHash::new(), vec![list(&["the"]), list(&["cot", "cat"]), list(&["sat"])])];
+        let best = model().best_sentences(&solutions, 5, 4, &|_, _| true);
+        assert_eq!(sentences(&best), ["the cat sat", "the cot sat"]);
+        assert!(best[0].score > best[1].score);
+    }
+
+    #[test]
+    fn updates_only_edited_transforms_and_agrees_with_a_fresh_ranking() {
+        let model = model();
+        let shared = list(&["cot", "cat"]);
+        let mut solutions = vec![
+            (TransformHash::from([('a', 'o')]), vec![list(&["the"]), shared.clone(), list(&["sat"])]),
+            (TransformHash::from([('b', 'c')]), vec![list(&["the"]), shared.clone(), list(&["sat", "cat"])]),
+        ];
+        let mut ranker = SentenceRanker::new(4);
+        ranker.update(&model, &solutions);
+        let before = ranker.best(&solutions, 5, &|_, _| true);
+        assert_eq!(sentences(&before)[0], "the cat sat");
+
+        // editing a list swaps in a new one, which is what marks a transform for searching again
+        solutions[0].1[1] = list(&["cot"]);
+        solutions.pop();
+        ranker.update(&model, &solutions);
+        assert_eq!(ranker.searched.len(), 1);
+        let after = ranker.best(&solutions, 5, &|_, _| true);
+        assert_eq!(sentences(&after), sentences(&model.best_sentences(&solutions, 5, 4, &|_, _| true)));
+        assert_eq!(sentences(&after), ["the cot sat"]);
+    }
+}
@@ -0,0 +1,24 @@
+//! Solving jipher puzzles: finding every transform and the words it allows, storing them in caches, and narrowing
+//! them down with `Words`. The TUI in main.rs is one frontend on top of this.
+//!
+//! A typical run parses a `puzzle::Puzzle`, loads a `dictionary::Dictionary` and hands both to `cache::solve`,
+//! which searches with `solutions::get_all_solutions` and saves the result. Later runs `cache::load` it back,
+//! checked against the puzzle and options, and build a `words::Words` to browse and eliminate from.
+//! Nothing here prints; how far a search has got is in its `progress::Progress`.
+
+pub mod alphabet;
+pub mod cache;
+pub mod checkpoint;
+pub mod constraints;
+pub mod dictionary;
+pub mod histogram;
+pub mod labels;
+pub mod language_model;
+mod list_memo;
+#[cfg(test)]
+mod test_support;
+pub mod progress;
+pub mod puzzle;
+pub mod solutions;
+mod word_index;
+pub mod words;
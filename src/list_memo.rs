@@ -0,0 +1,89 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::Arc;
+use crate::solutions::WordList;
+
+/// What shared word lists turned into under some edit, keyed on the list and a description of the edit.
+/// Transforms share lists, so going through this edits each one once and the edited lists end up shared too.
+/// Lists are told apart by address, so every list looked up is held on to here, which stops a new list
+/// from being given the same address while the memo is alive
+pub struct ListMemo<K> {
+    lists: HashMap<usize, (WordList, HashMap<K, WordList>)>,
+}
+
+impl<K: Eq + Hash> ListMemo<K> {
+    pub fn new() -> ListMemo<K> {
+        ListMemo { lists: HashMap::new() }
+    }
+
+    /// The list after the edit, if it's been done before
+    pub fn get(&self, list: &WordList, key: &K) -> Option<WordList> {
+        self.lists.get(&address(list))?.1.get(key).cloned()
+    }
+
+    /// Records the words an edit left, giving back the list to use from now on. Edits may only remove words,
+    /// so the same number of words means the original list. If the edit was already recorded that one is kept
+    pub fn insert(&mut self, list: &WordList, key: K, words: Vec<String>) -> WordList {
+        let edited = if words.len() == list.len() { list.clone() } else { Arc::new(words) };
+        self.lists.entry(address(list))
+            .or_insert_with(|| (list.clone(), HashMap::new()))
+            .1
+            .entry(key)
+            .or_insert(edited)
+            .clone()
+    }
+
+    /// The list after the edit, working it out the first time it comes up
+    pub fn edit(&mut self, list: &WordList, key: K, edit: impl FnOnce(&WordList) -> Vec<String>) -> WordList {
+        if let Some(edited) = self.get(list, &key) {
+            return edited;
+        }
+        let words = edit(list);
+        self.insert(list, key, words)
+    }
+}
+
+impl<K: Eq + Hash> Default for ListMemo<K> {
+    fn default() -> ListMemo<K> {
+        ListMemo::new()
+    }
+}
+
+fn address(list: &WordList) -> usize {
+    Arc::as_ptr(list) as usize
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn edits_each_list_once_and_keeps_unchanged_lists() {
+        let list: WordList = Arc::new(vec!["cat".to_string(), "cot".to_string()]);
+        let mut memo = ListMemo::new();
+        let mut edits = 0;
+        let mut without_cot = |list: &WordList| {
+            edits += 1;
+            list.iter().filter(|word| *word != "cot").cloned().collect()
+        };
+        let first = memo.edit(&list, "cot", &mut without_cot);
+        let second = memo.edit(&list, "cot", &mut without_cot);
+        assert_eq!(edits, 1);
+        assert!(Arc::ptr_eq(&first, &second));
+        assert_eq!(*first, ["cat"]);
+
+        let unchanged = memo.edit(&list, "dog", |list| list.to_vec());
+        assert!(Arc::ptr_eq(&unchanged, &list));
+    }
+
+    #[test]
+    fn holds_on_to_the_lists_it_was_given() {
+        let mut memo = ListMemo::new();
+        let list: WordList = Arc::new(vec!["cat".to_string()]);
+        memo.edit(&list, (), |_| vec![]);
+        // the memo's copy keeps the address taken even after everyone else lets go
+        let weak = Arc::downgrade(&list);
+        drop(list);
+        assert!(weak.upgrade().is_some());
+    }
+}
@@ -1,68 +1,727 @@
 use std::thread;
-use std::io::prelude::*;
-use serde_json;
-use solutions::TransformAndPossibilitiesList;
+use std::io::{BufWriter, Write};
+use std::sync::Arc;
+use serde::Serialize;
+use jipher::alphabet::BlankRule;
+use jipher::cache::{self, CacheError, CacheFile, LoadedCache};
+use jipher::dictionary::{Dictionary, DictionarySpec};
+use jipher::language_model::{self, LanguageModel};
+use jipher::labels;
+use jipher::progress::Progress;
+use jipher::puzzle::Puzzle;
+use jipher::solutions::{Pairing, SearchError, Possibilities, Shard, SolverOptions, Substitution, TransformAndPossibilitiesList, TransformHash};
+use jipher::words::{WordOrder, Words};
 mod interface;
-mod solutions;
-mod histogram;
-mod words;
 //go find george to toy ____ nam?
 
+/// What to do with the cache, picked by the first argument. Without one it's `Tui`, as it always was
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Command {
+    Solve,
+    Stats,
+    Query,
+    Export,
+    Best,
+    Tui,
+    Merge,
+    Convert,
+}
+
+impl Command {
+    fn name(self) -> &'static str {
+        match self {
+            Command::Solve => "solve",
+            Command::Stats => "stats",
+            Command::Query => "query",
+            Command::Export => "export",
+            Command::Best => "best",
+            Command::Tui => "tui",
+            Command::Merge => "merge",
+            Command::Convert => "convert",
+        }
+    }
+}
+
+struct Args {
+    command: Command,
+    path: String,
+    puzzle: Option<Puzzle>,
+    options: SolverOptions,
+    // the names of the options set on the command line, as `SolverOptions::differences` calls them
+    given: Vec<&'static str>,
+    // the paths after the first: merge's shard files, written to `path`, or where convert writes `path` to
+    more_paths: Vec<String>,
+    recompute: bool,
+    language_model: Option<String>,
+    beam_width: usize,
+    json: bool,
+    // query's 1-based position, how many words or sentences to list and in what order
+    position: Option<usize>,
+    top: Option<usize>,
+    order: Option<WordOrder>,
+    // where export writes, stdout if not given
+    output: Option<String>,
+}
+
+impl Args {
+    fn parse() -> Result<Args, Box<dyn std::error::Error>> {
+        Args::parse_from(std::env::args().skip(1))
+    }
+
+    /// Reads the arguments after the program name
+    fn parse_from(args: impl Iterator<Item = String>) -> Result<Args, Box<dyn std::error::Error>> {
+        //by default we'll check output.json.zstd for a prior output run
+        let mut paths = vec![];
+        let mut puzzle = None;
+        let mut options = SolverOptions::default();
+        let mut given = vec![];
+        let mut recompute = false;
+        let mut language_model = None;
+        let mut beam_width = None;
+        let mut dictionaries = vec![];
+        let mut json = false;
+        let mut position = None;
+        let mut top = None;
+        let mut order = None;
+        let mut output = None;
+        let mut args = args.peekable();
+        let command = match args.peek().map(String::as_str) {
+            Some("solve") => Some(Command::Solve),
+            Some("stats") => Some(Command::Stats),
+            Some("query") => Some(Command::Query),
+            Some("export") => Some(Command::Export),
+            Some("best") => Some(Command::Best),
+            Some("tui") => Some(Command::Tui),
+            Some("merge") => Some(Command::Merge),
+            Some("convert") => Some(Command::Convert),
+            _ => None,
+        };
+        if command.is_some() {
+            args.next();
+        }
+        // without a command it's the TUI, as it always was
+        let command = command.unwrap_or(Command::Tui);
+        while let Some(arg) = args.next() {
+            // merge and convert work on files rather than a search, so they take next to nothing
+            let file_only = match command {
+                Command::Merge => true,
+                Command::Convert => !matches!(arg.as_str(), "--puzzle" | "-p" | "--puzzle-file"),
+                _ => false,
+            };
+            if file_only && arg.starts_with('-') {
+                return Err(format!("{} doesn't take {}", command.name(), arg).into());
+            }
+            given.extend(match arg.as_str() {
+                "--pairs" => Some("pairs"),
+                "--all-pairings" => Some("pairing"),
+                "--sequential" => Some("substitution"),
+                "--pin" => Some("pinned"),
+                "--forbid" => Some("forbidden"),
+                "--shard" => Some("shard"),
+                "--dict" => Some("dictionaries"),
+                _ => None,
+            });
+            match arg.as_str() {
+                "--puzzle" | "-p" => {
+                    let text = args.next().ok_or("--puzzle needs a value")?;
+                    puzzle = Some(Puzzle::new(&text)?);
+                },
+                "--puzzle-file" => {
+                    let file = args.next().ok_or("--puzzle-file needs a path")?;
+                    puzzle = Some(Puzzle::from_file(&file)?);
+                },
+                "--pairs" => {
+                    let pairs = args.next().ok_or("--pairs needs a number")?;
+                    options.pairs = pairs.parse().map_err(|_| format!("--pairs expects a number, got {}", pairs))?;
+                    if options.pairs == 0 {
+                        return Err("--pairs must be at least 1".into());
+                    }
+                },
+                "--all-pairings" => options.pairing = Pairing::Permuted,
+                "--sequential" => options.substitution = Substitution::Sequential,
+                "--pin" => {
+                    let pairs = args.next().ok_or("--pin needs pairs like t=g")?;
+                    options.pinned.extend(parse_pairs(&pairs)?);
+                },
+                "--forbid" => {
+                    let pairs = args.next().ok_or("--forbid needs pairs like t=g")?;
+                    options.forbidden.extend(parse_pairs(&pairs)?);
+                },
+                "--dict" => {
+                    let dictionary = args.next().ok_or("--dict needs a tier and path like full:words.txt")?;
+                    dictionaries.push(DictionarySpec::parse(&dictionary)?);
+                },
+                "--lm" => language_model = Some(args.next().ok_or("--lm needs the path of an n-gram counts file")?),
+                "--beam" => {
+                    let width = args.next().ok_or("--beam needs a width")?;
+                    let width: usize = width.parse().map_err(|_| format!("--beam expects a number, got {}", width))?;
+                    if width == 0 {
+                        return Err("--beam must be at least 1".into());
+                    }
+                    beam_width = Some(width);
+                },
+                "--blanks" => {
+                    let rule = args.next().ok_or("--blanks needs a rule like only:aeiou, except:xyz, not-on-card, left or right")?;
+                    options.blanks.push(BlankRule::parse(&rule)?);
+                },
+                "--shard" => {
+                    let shard = args.next().ok_or("--shard needs a value like 2/4")?;
+                    options.shard = Some(parse_shard(&shard)?);
+                },
+                "--recompute" => recompute = true,
+                "--json" => json = true,
+                "--position" => {
+                    let number = args.next().ok_or("--position needs a number")?;
+                    let number: usize = number.parse().map_err(|_| format!("--position expects a number, got {}", number))?;
+                    if number == 0 {
+                        return Err("--position counts from 1".into());
+                    }
+                    position = Some(number);
+                },
+                "--top" => {
+                    let count = args.next().ok_or("--top needs a number of words")?;
+                    top = Some(count.parse().map_err(|_| format!("--top expects a number, got {}", count))?);
+                },
+                "--order" => {
+                    order = Some(match args.next().as_deref() {
+                        Some("combinations") => WordOrder::Combinations,
+                        Some("commonness") => WordOrder::Commonness,
+                        _ => return Err("--order needs combinations or commonness".into()),
+                    });
+                },
+                "--output" | "-o" => output = Some(args.next().ok_or("--output needs a path")?),
+                flag if flag.starts_with('-') => return Err(format!("unknown flag {}", flag).into()),
+                _ => paths.push(arg),
+            }
+        }
+        // merge and convert are the only commands that take more than the cache path
+        let mut paths = paths.into_iter();
+        let path = paths.next();
+        let more_paths = paths.collect::<Vec<_>>();
+        match command {
+            Command::Merge if more_paths.is_empty() => return Err("merge needs an output path followed by the shard files".into()),
+            Command::Convert if more_paths.len() != 1 => return Err("convert needs the cache to read and the path to write".into()),
+            Command::Merge | Command::Convert => {},
+            _ => if let Some(extra) = more_paths.first() {
+                return Err(format!("unexpected argument {}", extra).into());
+            },
+        }
+        if command == Command::Best && language_model.is_none() {
+            return Err("best needs a language model, pass one with --lm".into());
+        }
+        // flags that only mean something to some commands
+        let only = |allowed: &[Command], used: bool, message: &str| -> Result<(), Box<dyn std::error::Error>> {
+            if used && !allowed.contains(&command) {
+                return Err(message.into());
+            }
+            Ok(())
+        };
+        only(&[Command::Solve, Command::Stats, Command::Query, Command::Export, Command::Best], json, "--json is for solve, stats, query, export and best")?;
+        only(&[Command::Query], position.is_some() || order.is_some(), "--position and --order are for query")?;
+        only(&[Command::Query, Command::Best], top.is_some(), "--top is for query and best")?;
+        only(&[Command::Export], output.is_some(), "--output is for export")?;
+        only(&[Command::Solve, Command::Tui], recompute, "--recompute is for solve and tui, rebuild the cache with jipher solve --recompute")?;
+        only(&[Command::Tui, Command::Best], language_model.is_some() || beam_width.is_some(), "--lm and --beam are for tui and best")?;
+        if !dictionaries.is_empty() {
+            options.dictionaries = dictionaries;
+        }
+        Ok(Args {
+            command,
+            path: path.unwrap_or_else(|| "output.json.zstd".to_string()),
+            puzzle,
+            options,
+            given,
+            more_paths,
+            recompute,
+            language_model,
+            beam_width: beam_width.unwrap_or(language_model::DEFAULT_BEAM_WIDTH),
+            json,
+            position,
+            top,
+            order,
+            output,
+        })
+    }
+}
+
+/// Parses comma separated pairs like "a=y,j=t"
+fn parse_pairs(text: &str) -> Result<Vec<(char,char)>, Box<dyn std::error::Error>> {
+    text.split(',')
+        .map(|pair| {
+            let mut letters = pair.split('=').map(|side| side.trim().chars().collect::<Vec<_>>());
+            match (letters.next().as_deref(), letters.next().as_deref(), letters.next()) {
+                (Some(&[l]), Some(&[r]), None) if l.is_ascii_lowercase() && r.is_ascii_lowercase() => Ok((l, r)),
+                _ => Err(format!("could not read pair \"{}\", expected something like t=g", pair).into()),
+            }
+        })
+        .collect()
+}
+
+/// Parses a one-based shard like "2/4"
+fn parse_shard(text: &str) -> Result<Shard, Box<dyn std::error::Error>> {
+    let error = || format!("could not read shard \"{}\", expected something like 2/4", text);
+    let (index, count) = text.split_once('/').ok_or_else(error)?;
+    let index: usize = index.trim().parse().map_err(|_| error())?;
+    let count: usize = count.trim().parse().map_err(|_| error())?;
+    if index == 0 || index > count {
+        return Err(format!("shard {} is out of range, shards are numbered 1 to {}", index, count).into());
+    }
+    Ok(Shard { index: index - 1, count })
+}
+
+/// Stops over a cache that's there but can't be used, which needs the user to decide rather than a search to overwrite it
+fn refuse_cache(error: CacheError, command: Command) -> ! {
+    eprintln!("{}", error);
+    match (error, command) {
+        (CacheError::NoPuzzle(_), _) => eprintln!("Pass it with --puzzle or --puzzle-file"),
+        // only the commands that can search take --recompute
+        (_, Command::Solve | Command::Tui) => eprintln!("Pass --recompute to search again and replace it, or give another cache path"),
+        _ => eprintln!("Rebuild it with jipher solve --recompute, or give another cache path"),
+    }
+    std::process::exit(1);
+}
+
+fn load_dictionary(options: &SolverOptions) -> Dictionary {
+    Dictionary::load(&options.dictionaries).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    })
+}
 
 fn main() {
-    //by default we'll check output.json.zstd for a prior output run
-    let path = match std::env::args().nth(1) {
-        Some(input_path) => input_path,
-        None => "output.json.zstd".to_string(),
+    let args = match Args::parse() {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("{}", e);
+            eprintln!("Usage: jipher [tui] [CACHE_PATH] [--puzzle \"t. .i.d ...\" | --puzzle-file PATH] [SEARCH_OPTIONS] [--recompute] [--lm NGRAMS_PATH [--beam W]]
+       jipher solve [CACHE_PATH] [--puzzle \"t. .i.d ...\" | --puzzle-file PATH] [SEARCH_OPTIONS] [--recompute] [--json]
+       jipher stats [CACHE_PATH] [--blanks RULE]... [--json]
+       jipher query [CACHE_PATH] [--position N] [--top N] [--order combinations|commonness] [--blanks RULE]... [--json]
+       jipher export [CACHE_PATH] [--output PATH] [--blanks RULE]... [--json]
+       jipher best [CACHE_PATH] --lm NGRAMS_PATH [--top N] [--beam W] [--blanks RULE]... [--json]
+       jipher merge OUTPUT SHARD_FILES...
+       jipher convert OLD_CACHE NEW_CACHE [--puzzle \"t. .i.d ...\"]
+SEARCH_OPTIONS: [--pairs N] [--all-pairings] [--sequential] [--pin a=y,j=t] [--forbid t=t] [--dict TIER:PATH]... [--blanks RULE]... [--shard I/N]");
+            std::process::exit(2);
+        }
     };
-    
-    let result = match read_compressed_file(&path) {
-        // if we already have an output file then life is good
-        Ok(output) => {
-            println!("Prior solutions list found at {}", path);
-            output
+
+    match args.command {
+        Command::Solve => solve(&args),
+        Command::Tui => tui(args),
+        Command::Merge | Command::Convert => {
+            let result = match args.command {
+                Command::Merge => merge_shards(&args.path, &args.more_paths),
+                _ => convert_cache(&args.path, &args.more_paths[0], args.puzzle.as_ref()),
+            };
+            if let Err(e) = result {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
         },
-        // Otherwise Calculate one and write it to file
-        Err(_) => {
-            println!("No prior solutions file found at {}\n Will now begin calculating solution...", path);
+        Command::Stats | Command::Query | Command::Export | Command::Best => {
+            let Some(LoadedCache { puzzle, solutions, words: word_info, .. }) = load_cache(&args) else {
+                eprintln!("No solutions file at {}, build one with jipher solve", args.path);
+                std::process::exit(1);
+            };
+            let result = match args.command {
+                Command::Stats => {
+                    print_stats(&args, &Words::new(puzzle, solutions, word_info));
+                    Ok(())
+                },
+                Command::Query => print_query(&args, &Words::new(puzzle, solutions, word_info)),
+                Command::Best => print_best(&args, &puzzle, &solutions),
+                _ => export(&args, &solutions),
+            };
+            if let Err(e) = result {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        },
+    }
+}
+
+/// Reads the cache at the given path, checks it's for the puzzle and options asked for and re-checks it against any
+/// new blank rules. None when there's no cache there yet, or --recompute says to ignore it
+fn load_cache(args: &Args) -> Option<LoadedCache> {
+    if args.recompute {
+        return None;
+    }
+    let path = &args.path;
+    let loaded = match cache::load(path, args.puzzle.as_ref(), &args.options, &args.given) {
+        Ok(loaded) => loaded,
+        Err(CacheError::Missing(_)) => return None,
+        // a cache that's there but can't be used needs looking at, not overwriting
+        Err(e) => refuse_cache(e, args.command),
+    };
+    match &loaded.header {
+        // if we already have an output file then life is good, as long as it was made for this puzzle
+        Some(header) => {
+            match header.dictionaries_changed() {
+                Ok(true) => eprintln!("Warning: the dictionaries have changed since {} was built, search again to pick up the changes", path),
+                Ok(false) => {},
+                Err(e) => eprintln!("Could not check whether the dictionaries changed since {} was built: {}", path, e),
+            }
+            let options = &header.options;
+            eprintln!("Prior solutions list found at {} ({} pairs, {} substitution{})", path, options.pairs, options.substitution,
+                header.age().map_or(String::new(), |age| format!(", built {}", age)));
+            if let Some(shard) = options.shard {
+                eprintln!("Note this is only shard {} of the search", shard);
+            }
+        },
+        // older files don't say which puzzle they belong to, so we have to take the user's word for it
+        None => eprintln!("Prior solutions list found at {} (assuming it was built for the given puzzle with {} substitution)", path, SolverOptions::legacy().substitution),
+    }
+    if let Some(before) = loaded.before_blanks {
+        eprintln!("Checked the cached solutions against the blank rules ({}): {} of {} transforms still fit",
+            args.options.blanks.iter().map(|rule| rule.to_string()).collect::<Vec<_>>().join(", "), loaded.solutions.len(), before);
+    }
+    Some(loaded)
+}
+
+/// Searches for every solution to the puzzle and writes them to the cache path, exiting if the search is stopped
+/// or the cache can't be written
+fn search(args: &Args, puzzle: &Puzzle) -> CacheFile {
+    let path = &args.path;
+    let options = &args.options;
+    if let Err(e) = options.check(puzzle) {
+        eprintln!("{}", e);
+        std::process::exit(2);
+    }
+    if args.recompute {
+        eprintln!("Recomputing the solutions for {}\n Will now begin calculating solution...", path);
+    } else {
+        eprintln!("No prior solutions file found at {}\n Will now begin calculating solution...", path);
+    }
+    eprintln!("Puzzle: {}", puzzle);
+    eprintln!("Transforming {} of these letters: {} ({} substitution)", options.pairs, puzzle.known_letters().iter().collect::<String>(), options.substitution);
+    if !options.pinned.is_empty() || !options.forbidden.is_empty() {
+        eprintln!("Pinned pairs: {:?}, forbidden pairs: {:?}", options.pinned, options.forbidden);
+    }
+    for spec in &options.dictionaries {
+        eprintln!("Using {} dictionary {}", spec.tier, spec.path);
+    }
+    if !options.blanks.is_empty() {
+        eprintln!("Blanks must follow: {}", options.blanks.iter().map(|rule| rule.to_string()).collect::<Vec<_>>().join(", "));
+    }
+    if let Some(shard) = options.shard {
+        eprintln!("Searching shard {} of the L sides", shard);
+    }
+    let dictionary = load_dictionary(options);
+    thread::sleep(std::time::Duration::from_secs(1));
+
+    // Ctrl-C asks the workers to wrap up, a second one gives up on waiting for them
+    let progress = Arc::new(Progress::new());
+    let handler_progress = progress.clone();
+    ctrlc::set_handler(move || {
+        if handler_progress.is_cancelled() {
+            std::process::exit(130);
+        }
+        handler_progress.cancel();
+    }).expect("could not set the Ctrl-C handler");
+    let printer = print_progress(progress.clone());
+
+    // finished L sides are saved to a checkpoint as we go, so an interrupted run can pick up where it left off
+    let result = cache::solve(path, puzzle, options, &dictionary, &progress);
+    // it may have stopped before it started, in which case the printer is still waiting
+    progress.finish();
+    printer.join().unwrap();
+    let snapshot = progress.snapshot();
+    match result {
+        Ok(cache) => {
+            if snapshot.resumed > 0 {
+                eprintln!("Resumed from the checkpoint with {} L sides already done", snapshot.resumed);
+            }
+            eprintln!("{} L sides skipped because a word they can't change has no matches", snapshot.skipped);
+            cache
+        },
+        Err(SearchError::Cancelled) => {
+            eprintln!("Stopped, {} L sides are saved in {} and will be skipped next time", snapshot.completed, cache::checkpoint_path(path));
+            std::process::exit(130);
+        },
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        },
+    }
+}
+
+/// Prints a status line to stderr every second until the search finishes or is cancelled.
+/// This runs on its own thread rather than the rayon pool so it can't starve the workers
+fn print_progress(progress: Arc<Progress>) -> thread::JoinHandle<()> {
+    thread::spawn(move || {
+        // wait for the solver to say how much work there is
+        while !progress.is_started() && !progress.is_finished() {
+            thread::sleep(std::time::Duration::from_millis(100));
+        }
+        if !progress.is_started() {
+            return;
+        }
+        eprintln!();
+        loop {
+            let snapshot = progress.snapshot();
+            eprint!("\r{}", snapshot);
+            std::io::stderr().flush().unwrap();
+            if snapshot.finished || snapshot.cancelled {
+                eprintln!();
+                return;
+            }
             thread::sleep(std::time::Duration::from_secs(1));
-            let output = solutions::get_all_solutions();
-            write_compressed_file(&"output.json.zstd".to_string(), &output);
-            output
         }
+    })
+}
+
+/// `jipher solve`: makes sure there's a cache for the puzzle, only searching when there isn't one yet
+fn solve(args: &Args) {
+    // blank rules the cache wasn't built with only narrow what's loaded, the file keeps every transform
+    let (puzzle, transforms, fitting, searched) = match load_cache(args) {
+        Some(loaded) => {
+            let fitting = loaded.before_blanks.map(|_| loaded.solutions.len());
+            (loaded.puzzle, loaded.before_blanks.unwrap_or(loaded.solutions.len()), fitting, false)
+        },
+        None => {
+            let Some(puzzle) = &args.puzzle else {
+                eprintln!("No prior solutions file found at {} and no puzzle given, pass one with --puzzle or --puzzle-file", args.path);
+                std::process::exit(1);
+            };
+            let cache = search(args, puzzle);
+            (puzzle.clone(), cache.solutions.len(), None, true)
+        },
     };
+    if args.json {
+        println!("{}", serde_json::json!({
+            "path": args.path,
+            "puzzle": puzzle.text(),
+            "transforms": transforms,
+            "fitting_blanks": fitting,
+            "searched": searched,
+        }));
+    } else if searched {
+        println!("Wrote {} transforms to {}", transforms, args.path);
+    } else {
+        let fitting = fitting.map_or(String::new(), |fitting| format!(" ({} fit the blank rules)", fitting));
+        println!("{} already has {} transforms for this puzzle{}, pass --recompute to search again", args.path, transforms, fitting);
+    }
+}
 
-    interface::run(result);    
+/// `jipher stats`: the totals from the TUI's Home screen
+fn print_stats(args: &Args, words: &Words) {
+    if args.json {
+        println!("{}", serde_json::json!({
+            "puzzle": words.puzzle().text(),
+            "words": words.total_words(),
+            "transforms": words.total_transforms(),
+            "combinations": words.total_combinations(),
+            "combinations_estimated": words.combinations_estimated(),
+        }));
+    } else {
+        println!("Puzzle: {}", words.puzzle());
+        println!("Total Words: {}", words.total_words());
+        println!("Total Possible Transforms: {}", words.total_transforms());
+        println!("Total Possible Combinations: {}{}", if words.combinations_estimated() { "~" } else { "" }, words.total_combinations());
+    }
 }
 
-fn read_compressed_file(path: &String) -> Result<TransformAndPossibilitiesList,Box<dyn std::error::Error>> {
-    let file = std::fs::File::open(path)?;
-    println!("File opened");
-    let mut decompressed = String::new();
-    let mut writer = zstd::stream::Decoder::new(file)?;
-    println!("decompressor opened");
-    writer.read_to_string(&mut decompressed)?;
+/// `jipher query`: the top words over the whole sentence, counted in combinations, or at one position, counted in
+/// the transforms that allow them there
+fn print_query(args: &Args, words: &Words) -> Result<(), Box<dyn std::error::Error>> {
+    let top = args.top.unwrap_or(20);
+    let order = args.order.unwrap_or(WordOrder::Combinations);
+    let (listed, counting) = match args.position {
+        None => (words.get_top(0..top, order, |_| true), "combinations"),
+        Some(position) => {
+            let histogram = words.positional_histograms().get(position - 1)
+                .ok_or_else(|| format!("--position must be between 1 and {}", words.sentence_length()))?;
+            (words.sorted(histogram, order).into_iter().take(top).collect(), "transforms")
+        },
+    };
+    if args.json {
+        let listed = listed.iter()
+            .map(|(word, count)| serde_json::json!({ "word": word, "count": count, "rank": words.rank(word), "tier": words.tier(word) }))
+            .collect::<Vec<_>>();
+        println!("{}", serde_json::json!({
+            "position": args.position,
+            "order": order.to_string(),
+            "counting": counting,
+            "words": listed,
+        }));
+    } else {
+        for (n, (word, count)) in listed.iter().enumerate() {
+            println!("{: >4}. {: <16} {} {}", n + 1, word, count, counting);
+        }
+    }
+    Ok(())
+}
 
-    println!("File read");
-    let result = serde_json::from_str(
-        decompressed.as_str()
-    )?;
+/// `jipher best`: the most likely sentences across all the transforms, as the TUI's sentence view ranks them
+fn print_best(args: &Args, puzzle: &Puzzle, solutions: &TransformAndPossibilitiesList) -> Result<(), Box<dyn std::error::Error>> {
+    let language_model = LanguageModel::load(args.language_model.as_deref().unwrap_or_default())?;
+    let labels = labels::Labels::new(puzzle);
+    let ranked = language_model.best_sentences(solutions, args.top.unwrap_or(10), args.beam_width, &|_, sentence| labels.check(sentence));
+    if args.json {
+        let ranked = ranked.iter()
+            .map(|sentence| serde_json::json!({ "words": sentence.words, "score": sentence.score, "transform": sentence.transform }))
+            .collect::<Vec<_>>();
+        println!("{}", serde_json::json!({
+            "puzzle": puzzle.text(),
+            "beam_width": args.beam_width,
+            "sentences": ranked,
+        }));
+    } else {
+        for (rank, sentence) in ranked.iter().enumerate() {
+            println!("{: >4}. {: <40} {: >8.2}  {:?}", rank + 1, sentence.words.join(" "), sentence.score, sentence.transform);
+        }
+    }
+    Ok(())
+}
 
-    println!("Processed and loaded {}",path);
-    Ok(result)
+/// `jipher export`: every transform and the words it allows at each position. The text version has a line per
+/// transform, its pairs like --pin takes them and then each position's words with | between positions
+fn export(args: &Args, solutions: &TransformAndPossibilitiesList) -> Result<(), Box<dyn std::error::Error>> {
+    let mut out: Box<dyn Write> = match &args.output {
+        Some(path) => Box::new(BufWriter::new(std::fs::File::create(path).map_err(|e| format!("could not write {}: {}", path, e))?)),
+        None => Box::new(BufWriter::new(std::io::stdout().lock())),
+    };
+    if args.json {
+        #[derive(Serialize)]
+        struct Exported<'a> {
+            transform: &'a TransformHash,
+            words: &'a Possibilities,
+        }
+        let exported = solutions.iter().map(|(transform, words)| Exported { transform, words }).collect::<Vec<_>>();
+        serde_json::to_writer(&mut out, &exported)?;
+        writeln!(out)?;
+    } else {
+        for (transform, possibilities) in solutions {
+            let pairs = transform.iter().map(|(l, r)| format!("{}={}", l, r)).collect::<Vec<_>>().join(",");
+            let lists = possibilities.iter().map(|list| list.join(" ")).collect::<Vec<_>>().join(" | ");
+            writeln!(out, "{}\t{}", pairs, lists)?;
+        }
+    }
+    out.flush()?;
+    if let Some(path) = &args.output {
+        eprintln!("Exported {} transforms to {}", solutions.len(), path);
+    }
+    Ok(())
+}
+
+/// `jipher tui`, which is also what runs without a command: loads the cache, or searches to build one, and opens the TUI
+fn tui(args: Args) {
+    let (puzzle, result, word_info) = match load_cache(&args) {
+        Some(loaded) => (loaded.puzzle, loaded.solutions, loaded.words),
+        // Otherwise Calculate one and write it to file
+        None => {
+            let Some(puzzle) = args.puzzle.clone() else {
+                eprintln!("No prior solutions file found at {} and no puzzle given, pass one with --puzzle or --puzzle-file", args.path);
+                std::process::exit(1);
+            };
+            let cache = search(&args, &puzzle);
+            if let Some(shard) = cache.header.options.shard {
+                println!("Wrote shard {} to {}, combine the shards with jipher merge once they are all done", shard, args.path);
+                return;
+            }
+            (puzzle, cache.solutions, cache.words)
+        },
+    };
+
+    let language_model = args.language_model.map(|path| {
+        LanguageModel::load(&path).unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        })
+    });
+
+    interface::run(puzzle, result, word_info, language_model, args.beam_width);
 }
 
-fn write_compressed_file(path: &String, filedata: &TransformAndPossibilitiesList) {
-    let mut file = std::fs::OpenOptions::new()
-        .write(true)
-        .create(true)
-        .open(path)
-        .unwrap();
+/// Rewrites a cache in the current format. The oldest caches don't record their puzzle, so it has to be given
+fn convert_cache(input: &str, output: &str, puzzle: Option<&Puzzle>) -> Result<(), Box<dyn std::error::Error>> {
+    let cache = cache::convert(input, output, puzzle).map_err(|e| match e.downcast_ref::<CacheError>() {
+        Some(CacheError::NoPuzzle(_)) => format!("{}, pass it with --puzzle or --puzzle-file", e),
+        _ => format!("could not convert {}: {}", input, e),
+    })?;
+    println!("Converted {} from format version {} to {} in {} ({} transforms)", input, cache.header.format_version, cache::FORMAT_VERSION, output, cache.solutions.len());
+    Ok(())
+}
+
+/// Combines the caches written by sharded runs into one, the same as if the search had been run in one go
+fn merge_shards(output: &str, inputs: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let merged = cache::merge_shards(inputs)?;
+    cache::write(output, &merged)?;
+    println!("Merged {} shards into {} ({} transforms)", inputs.len(), output, merged.solutions.len());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(line: &str) -> Result<Args, String> {
+        Args::parse_from(line.split_whitespace().map(str::to_string)).map_err(|e| e.to_string())
+    }
+
+    fn error(line: &str) -> String {
+        parse(line).err().unwrap_or_else(|| panic!("{} was accepted", line))
+    }
+
+    #[test]
+    fn shards_are_numbered_from_one() {
+        assert_eq!(parse_shard("1/3").unwrap(), Shard { index: 0, count: 3 });
+        assert_eq!(parse_shard(" 3 / 3").unwrap(), Shard { index: 2, count: 3 });
+        assert!(parse_shard("0/3").unwrap_err().to_string().contains("out of range"));
+        assert!(parse_shard("4/3").unwrap_err().to_string().contains("out of range"));
+        assert!(parse_shard("2/0").unwrap_err().to_string().contains("out of range"));
+        assert!(parse_shard("2").unwrap_err().to_string().contains("expected something like 2/4"));
+    }
+
+    #[test]
+    fn pairs_are_single_letters() {
+        assert_eq!(parse_pairs("t=g, a = y").unwrap(), [('t', 'g'), ('a', 'y')]);
+        assert!(parse_pairs("t=g,ab=c").unwrap_err().to_string().contains("\"ab=c\""));
+        assert!(parse_pairs("t=G").is_err());
+        assert!(parse_pairs("t=g=h").is_err());
+        assert!(parse_pairs("").is_err());
+    }
 
-    let serialized = serde_json::to_string(filedata).unwrap();
+    #[test]
+    fn without_a_command_it_is_the_tui() {
+        let args = parse("cache.zst --pairs 3 --all-pairings --blanks only:aeiou").unwrap();
+        assert!(args.command == Command::Tui);
+        assert_eq!(args.path, "cache.zst");
+        assert_eq!(args.options.pairs, 3);
+        // blank rules only narrow what's loaded, so they're never compared with the cache's
+        assert_eq!(args.given, ["pairs", "pairing"]);
+        assert_eq!(args.beam_width, language_model::DEFAULT_BEAM_WIDTH);
+        assert_eq!(parse("").unwrap().path, "output.json.zstd");
+    }
 
-    let encoded = zstd::encode_all(serialized.as_bytes(), 0).unwrap();
+    #[test]
+    fn flags_are_checked_against_the_command() {
+        assert!(parse("solve --json --recompute --pairs 4").is_ok());
+        assert!(parse("query --position 2 --top 5 --order commonness --json").is_ok());
+        assert!(parse("best --lm counts.txt --beam 8 --top 3").is_ok());
+        assert_eq!(error("stats --top 3"), "--top is for query and best");
+        assert_eq!(error("export --position 1"), "--position and --order are for query");
+        assert_eq!(error("query --output out.txt"), "--output is for export");
+        assert!(error("stats --recompute").starts_with("--recompute is for solve and tui"));
+        assert_eq!(error("solve --beam 4"), "--lm and --beam are for tui and best");
+        assert_eq!(error("tui --json"), "--json is for solve, stats, query, export and best");
+        assert_eq!(error("best"), "best needs a language model, pass one with --lm");
+        assert_eq!(error("query --position 0"), "--position counts from 1");
+        assert_eq!(error("solve --pairs 0"), "--pairs must be at least 1");
+        assert_eq!(error("solve --frobnicate"), "unknown flag --frobnicate");
+        assert_eq!(error("solve one.zst two.zst"), "unexpected argument two.zst");
+    }
 
-    file.write_all(&encoded).unwrap();
+    #[test]
+    fn merge_and_convert_take_paths() {
+        let merge = parse("merge all.zst a.zst b.zst").unwrap();
+        assert_eq!((merge.path.as_str(), merge.more_paths), ("all.zst", vec!["a.zst".to_string(), "b.zst".to_string()]));
+        assert_eq!(error("merge all.zst"), "merge needs an output path followed by the shard files");
+        assert_eq!(error("merge all.zst a.zst --pairs 3"), "merge doesn't take --pairs");
 
+        assert!(parse("convert old.zst new.zst --puzzle th.").unwrap().puzzle.is_some());
+        assert_eq!(error("convert old.zst"), "convert needs the cache to read and the path to write");
+        assert_eq!(error("convert old.zst new.zst --json"), "convert doesn't take --json");
+    }
 }
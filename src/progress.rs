@@ -0,0 +1,136 @@
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// How far along a search is. The solver updates it from its worker threads and anything else
+/// (the stderr printer, the TUI) can poll `snapshot` whenever it wants to show where things are
+#[derive(Default)]
+pub struct Progress {
+    total: AtomicUsize,
+    // L sides a previous run already finished, which don't count towards the rate
+    resumed: AtomicUsize,
+    completed: AtomicUsize,
+    survivors: AtomicUsize,
+    skipped: AtomicUsize,
+    cancelled: AtomicBool,
+    finished: AtomicBool,
+    started: Mutex<Option<Instant>>,
+}
+
+/// A point-in-time view of a search's progress
+#[derive(Clone, Debug)]
+pub struct ProgressSnapshot {
+    /// L sides done, including any resumed from a checkpoint
+    pub completed: usize,
+    /// L sides a previous run already finished
+    pub resumed: usize,
+    pub total: usize,
+    /// L sides thrown out whole because a word they can't change has no matches
+    pub skipped: usize,
+    /// Transforms that have made it through so far
+    pub survivors: usize,
+    pub elapsed: Duration,
+    /// L sides per second over this run
+    pub rate: f64,
+    pub eta: Option<Duration>,
+    pub cancelled: bool,
+    pub finished: bool,
+}
+
+impl Progress {
+    pub fn new() -> Progress {
+        Progress::default()
+    }
+
+    /// Called by the solver once it knows how much work there is and how much was already done
+    pub fn start(&self, total: usize, resumed: usize, resumed_survivors: usize) {
+        self.total.store(total, Ordering::Relaxed);
+        self.resumed.store(resumed, Ordering::Relaxed);
+        self.completed.store(0, Ordering::Relaxed);
+        self.survivors.store(resumed_survivors, Ordering::Relaxed);
+        self.skipped.store(0, Ordering::Relaxed);
+        *self.started.lock().unwrap() = Some(Instant::now());
+    }
+
+    pub fn complete_one(&self, survivors: usize) {
+        self.survivors.fetch_add(survivors, Ordering::Relaxed);
+        self.completed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn skip_one(&self) {
+        self.skipped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn finish(&self) {
+        self.finished.store(true, Ordering::Relaxed);
+    }
+
+    /// Asks the workers to stop; they drop whatever L side they are part way through
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    pub fn snapshot(&self) -> ProgressSnapshot {
+        let elapsed = self.started.lock().unwrap().map_or(Duration::ZERO, |started| started.elapsed());
+        let completed = self.completed.load(Ordering::Relaxed);
+        let resumed = self.resumed.load(Ordering::Relaxed);
+        let total = self.total.load(Ordering::Relaxed);
+        let rate = if elapsed.is_zero() { 0.0 } else { completed as f64 / elapsed.as_secs_f64() };
+        let remaining = total.saturating_sub(completed + resumed);
+        let eta = if remaining == 0 {
+            Some(Duration::ZERO)
+        } else if rate > 0.0 {
+            Some(Duration::from_secs_f64(remaining as f64 / rate))
+        } else {
+            None
+        };
+
+        ProgressSnapshot {
+            completed: completed + resumed,
+            resumed,
+            total,
+            skipped: self.skipped.load(Ordering::Relaxed),
+            survivors: self.survivors.load(Ordering::Relaxed),
+            elapsed,
+            rate,
+            eta,
+            cancelled: self.is_cancelled(),
+            finished: self.finished.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Whether the solver has said how much work there is yet
+    pub fn is_started(&self) -> bool {
+        self.started.lock().unwrap().is_some()
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.finished.load(Ordering::Relaxed)
+    }
+}
+
+fn format_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    match seconds {
+        0..60 => format!("{}s", seconds),
+        60..3600 => format!("{}m{:02}s", seconds / 60, seconds % 60),
+        _ => format!("{}h{:02}m", seconds / 3600, seconds % 3600 / 60),
+    }
+}
+
+impl std::fmt::Display for ProgressSnapshot {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{} L sides in {} | {:.2}/s | ETA {} | {} transforms so far   ",
+            self.completed,
+            self.total,
+            format_duration(self.elapsed),
+            self.rate,
+            self.eta.map_or("unknown".to_string(), format_duration),
+            self.survivors,
+        )
+    }
+}
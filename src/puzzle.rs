@@ -0,0 +1,65 @@
+use std::error::Error;
+
+/// The text of a puzzle card: space separated words where '.' marks a blank. A digit marks a labelled blank,
+/// which holds the same letter as every other blank with that digit and a different one from any other digit
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Puzzle {
+    text: String,
+}
+
+impl Puzzle {
+    /// Parses a puzzle, normalising whitespace and accepting '_' as a blank like index.js does
+    pub fn new(text: &str) -> Result<Puzzle, Box<dyn Error>> {
+        let text = text
+            .split_whitespace()
+            .map(|word| word.to_lowercase().replace('_', "."))
+            .collect::<Vec<_>>()
+            .join(" ");
+
+        if text.is_empty() {
+            return Err("puzzle is empty".into());
+        }
+        if let Some(c) = text.chars().find(|c| !(c.is_ascii_lowercase() || c.is_ascii_digit() || *c == '.' || *c == ' ')) {
+            return Err(format!("puzzle contains unsupported character '{}'", c).into());
+        }
+
+        Ok(Puzzle { text })
+    }
+
+    /// Reads a puzzle from a file. Lines starting with '#' are treated as comments
+    pub fn from_file(path: &str) -> Result<Puzzle, Box<dyn Error>> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| format!("could not read puzzle file {}: {}", path, e))?;
+        let text = content
+            .lines()
+            .filter(|line| !line.trim_start().starts_with('#'))
+            .collect::<Vec<_>>()
+            .join(" ");
+        Puzzle::new(&text)
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    pub fn words(&self) -> impl Iterator<Item = &str> {
+        self.text.split(' ')
+    }
+
+    /// Every letter visible on the card, in order of first appearance
+    pub fn known_letters(&self) -> Vec<char> {
+        let mut letters = vec![];
+        for c in self.text.chars().filter(|c| c.is_ascii_lowercase()) {
+            if !letters.contains(&c) {
+                letters.push(c);
+            }
+        }
+        letters
+    }
+}
+
+impl std::fmt::Display for Puzzle {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.text)
+    }
+}
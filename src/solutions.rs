@@ -1,159 +1,561 @@
 use combinations::Combinations;
-use std::{collections::HashMap, time::Instant, time::Duration};
+use std::collections::{BTreeMap, HashMap};
 use rayon::iter::ParallelBridge;
 use rayon::prelude::ParallelIterator;
-use std::io::prelude::*;
-use std::sync::atomic;
-use regex::Regex;
+use std::sync::{Arc, RwLock};
+use serde::{Deserialize, Serialize};
+use crate::alphabet::{Alphabet, BlankRule};
+use crate::checkpoint::Checkpoint;
+use crate::constraints::Blanks;
+use crate::dictionary::{Dictionary, DictionarySpec};
+use crate::labels::Labels;
+use crate::list_memo::ListMemo;
+use crate::progress::Progress;
+use crate::puzzle::Puzzle;
+use crate::word_index::WordIndex;
 
-pub type TransformHash = HashMap<char,char>;
-pub type Possibilities = Vec<Vec<String>>;
+pub type TransformHash = BTreeMap<char,char>;
+/// The words that fit one position, shared between every transform that leaves that position with the same pattern
+pub type WordList = Arc<Vec<String>>;
+pub type Possibilities = Vec<WordList>;
 pub type TransformAndPossibilities = (TransformHash,Possibilities);
 
 pub type TransformAndPossibilitiesList =  Vec<TransformAndPossibilities>;
 
-static COUNT : atomic::AtomicUsize = atomic::AtomicUsize::new(0);
-const PUZZLE : &str = "t. .i.d t..rt. .o .o. t... na. ..ne y.. .w.lm. ..cy d.ne";
+/// How the chosen left letters are matched up with the chosen right letters
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Pairing {
+    /// Both sides sorted and zipped, so only the order-preserving mapping is tried
+    #[default]
+    Sorted,
+    /// Every permutation of the right side is tried against each left combination
+    Permuted,
+}
+
+/// How the pairs of a transform are applied to the puzzle
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Substitution {
+    /// Every pair is applied at once, so e➔n, n➔p turns e into n and n into p
+    #[default]
+    Simultaneous,
+    /// Pairs are applied one after another, so e➔n, n➔p turns every e into p
+    Sequential,
+}
+
+impl std::fmt::Display for Substitution {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Substitution::Simultaneous => write!(f, "simultaneous"),
+            Substitution::Sequential => write!(f, "sequential"),
+        }
+    }
+}
+
+/// One of `count` contiguous slices of the L side combinations, so a search can be split across machines
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Shard {
+    /// Zero-based, though it's shown to people one-based
+    pub index: usize,
+    pub count: usize,
+}
+
+impl Shard {
+    /// The L side indices this shard covers out of `total`
+    pub fn range(&self, total: usize) -> std::ops::Range<usize> {
+        self.index * total / self.count..(self.index + 1) * total / self.count
+    }
+}
+
+impl std::fmt::Display for Shard {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}", self.index + 1, self.count)
+    }
+}
+
+/// Parameters of the search that aren't part of the puzzle itself
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SolverOptions {
+    /// How many letters each transform substitutes
+    pub pairs: usize,
+    pub pairing: Pairing,
+    pub substitution: Substitution,
+    /// Pairs known from other cards that every transform must contain
+    #[serde(default)]
+    pub pinned: Vec<(char,char)>,
+    /// Pairs that no transform may contain
+    #[serde(default)]
+    pub forbidden: Vec<(char,char)>,
+    /// Only search this part of the L sides, for merging with the other shards later
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub shard: Option<Shard>,
+    /// Word lists to search, most common first
+    #[serde(default = "DictionarySpec::default_list")]
+    pub dictionaries: Vec<DictionarySpec>,
+    /// Which letters may fill the blanks, any letter if empty
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub blanks: Vec<BlankRule>,
+}
+
+impl Default for SolverOptions {
+    fn default() -> SolverOptions {
+        SolverOptions {
+            pairs: 5,
+            pairing: Pairing::default(),
+            substitution: Substitution::default(),
+            pinned: vec![],
+            forbidden: vec![],
+            shard: None,
+            dictionaries: DictionarySpec::default_list(),
+            blanks: vec![],
+        }
+    }
+}
+
+impl SolverOptions {
+    /// The options every cache was built with before they were configurable
+    pub fn legacy() -> SolverOptions {
+        SolverOptions {
+            substitution: Substitution::Sequential,
+            ..SolverOptions::default()
+        }
+    }
+
+    /// What differs between two sets of options, as the name of each option that differs and both values
+    pub fn differences(&self, other: &SolverOptions) -> Vec<(&'static str, String, String)> {
+        let mut differences = vec![];
+        let mut compare = |name: &'static str, ours: String, theirs: String| if ours != theirs {
+            differences.push((name, ours, theirs));
+        };
+        compare("pairs", self.pairs.to_string(), other.pairs.to_string());
+        compare("pairing", format!("{:?}", self.pairing).to_lowercase(), format!("{:?}", other.pairing).to_lowercase());
+        compare("substitution", self.substitution.to_string(), other.substitution.to_string());
+        compare("pinned", format!("{:?}", self.pinned), format!("{:?}", other.pinned));
+        compare("forbidden", format!("{:?}", self.forbidden), format!("{:?}", other.forbidden));
+        let shard = |shard: Option<Shard>| shard.map_or("whole search".to_string(), |shard| shard.to_string());
+        compare("shard", shard(self.shard), shard(other.shard));
+        let dictionaries = |specs: &[DictionarySpec]| specs.iter().map(|spec| format!("{}:{}", spec.tier, spec.path)).collect::<Vec<_>>().join(" ");
+        compare("dictionaries", dictionaries(&self.dictionaries), dictionaries(&other.dictionaries));
+        let blanks = |rules: &[BlankRule]| if rules.is_empty() { "any".to_string() } else { rules.iter().map(BlankRule::to_string).collect::<Vec<_>>().join(" ") };
+        compare("blanks", blanks(&self.blanks), blanks(&other.blanks));
+        differences
+    }
+
+    /// Checks the pair count, pinned and forbidden pairs make sense for this puzzle
+    pub fn check(&self, puzzle: &Puzzle) -> Result<(), Box<dyn std::error::Error>> {
+        let known_letters = puzzle.known_letters();
+        // each pair takes a different letter off the card, so there have to be enough of them
+        if self.pairs > known_letters.len() {
+            return Err(format!("transforms of {} pairs need {} different letters on the card, but the puzzle only has {}",
+                self.pairs, self.pairs, known_letters.len()).into());
+        }
+        if self.pinned.len() > self.pairs {
+            return Err(format!("{} pairs are pinned but transforms only have {}", self.pinned.len(), self.pairs).into());
+        }
+        for (i, (l, r)) in self.pinned.iter().enumerate() {
+            if !known_letters.contains(l) {
+                return Err(format!("pinned pair {}={} uses a letter that isn't on the card", l, r).into());
+            }
+            if self.pinned[..i].iter().any(|(l2, r2)| l2 == l || r2 == r) {
+                return Err(format!("pinned pair {}={} reuses a letter from another pinned pair", l, r).into());
+            }
+            if self.forbidden.contains(&(*l, *r)) {
+                return Err(format!("pair {}={} is both pinned and forbidden", l, r).into());
+            }
+        }
+        Ok(())
+    }
+}
 
 struct AllTransforms {}
 impl AllTransforms {
-    fn parallel_map(inner: impl Fn(&Transform) -> Option<TransformAndPossibilities> + Sync) -> TransformAndPossibilitiesList {
-        //spawn a thread to output progress info
-        rayon::spawn(|| {
-            let mut count = 0;
-            let now = Instant::now();
-            println!("");
-            while count < 1287 {
-                count = COUNT.load(atomic::Ordering::Relaxed);
-                print!("\r{}/1287 completed in {} seconds",
-                    COUNT.load(atomic::Ordering::Relaxed),
-                    now.elapsed().as_secs()
-                );
-                std::io::stdout().flush().unwrap();
-                std::thread::sleep(Duration::from_secs(1));
-            }
-        });
+    /// Runs `inner` over every transform, skipping whole L sides that `viable_left` rules out or that the checkpoint already has.
+    /// Results come back keyed by the index of their L side so the output order doesn't depend on thread scheduling
+    /// A checkpoint that can't be written stops the search, since carrying on would lose the work it was meant to keep
+    fn parallel_map(left_letters: &[char], options: &SolverOptions, checkpoint: Option<&Checkpoint>, progress: &Progress, viable_left: impl Fn(&[char]) -> bool + Sync, inner: impl Fn(&Transform) -> Option<TransformAndPossibilities> + Sync) -> Result<Vec<(usize, TransformAndPossibilitiesList)>, SearchError> {
+        let (free_left, free_right, free_pairs) = AllTransforms::free_letters(left_letters, options);
+
+        // none when the puzzle has too few letters for this many pairs, which `SolverOptions::check` warns about
+        let all_lefts = binomial(free_left.len(), free_pairs);
+        let range = options.shard.map_or(0..all_lefts, |shard| shard.range(all_lefts));
+        let total = range.len();
+        progress.start(total,
+            checkpoint.map_or(0, Checkpoint::finished_count),
+            checkpoint.map_or(0, Checkpoint::resumed_survivors));
         
         //iterate over all possible L sides of the transform in parallel
-        let left_combinator = Combinations::new(Transform::L_LETTERS.into(),5);
-        left_combinator.par_bridge()
-            .map(|left| {
-                // Given our L side of the transform, try all possible R-side combinations
-                let right_combinator = Combinations::new(Transform::R_LETTERS.into(), 5);
-                let result = right_combinator.filter_map(|right| {
-                    let transform = Transform::new(&left,&right);
-                    //apply our money function to this transform and include only if it's Some(value)
-                    inner(&transform)
-                })
-                .collect::<TransformAndPossibilitiesList>();
-                COUNT.fetch_add(1, atomic::Ordering::Relaxed);
-                result
+        let left_combinator = combinations(&free_left,free_pairs).enumerate()
+            .filter(|(index, _)| range.contains(index) && !checkpoint.is_some_and(|c| c.is_finished(*index)));
+        let mut results = left_combinator.par_bridge()
+            .filter_map(|(index, left)| {
+                if progress.is_cancelled() {
+                    return None;
+                }
+                let result = if viable_left(&left) {
+                    AllTransforms::with_left(&left, &free_right, options)
+                        .take_while(|_| !progress.is_cancelled())
+                        //apply our money function to this transform and include only if it's Some(value)
+                        .filter_map(|transform| inner(&transform))
+                        .collect::<TransformAndPossibilitiesList>()
+                } else {
+                    vec![]
+                };
+                // a cancelled L side is incomplete, so it mustn't reach the checkpoint
+                if progress.is_cancelled() {
+                    return None;
+                }
+                if let Some(checkpoint) = checkpoint && let Err(e) = checkpoint.record(index, &result) {
+                    return Some(Err(SearchError::Checkpoint(format!("could not write checkpoint {}: {}", checkpoint.path(), e))));
+                }
+                progress.complete_one(result.len());
+                Some(Ok((index, result)))
             })
-            .flatten()
-            .collect()
+            .collect::<Result<Vec<_>, _>>()?;
+        results.sort_by_key(|(index, _)| *index);
+        Ok(results)
     }
-}
 
-pub fn get_all_solutions() -> TransformAndPossibilitiesList {
-    let word_list = {
-        use std::fs::read_to_string;
-        use std::path::Path;
-    
-        let path = Path::new("./node_modules/word-list/commons.txt");
-        let file_content: String = read_to_string(path).unwrap();
-        let all_words: Vec<String> = file_content
-            .lines()  // split the string into an iterator of string slices
-            .map(String::from)
-            .collect();
-        let words: HashMap<usize,String> = vec![1,2,3,4,5,6]
-            .into_iter()
-            .map(|word_length| {
-                (word_length, all_words.clone()
-                                        .into_iter()
-                                        .filter(|word| word.len() == word_length)
-                                        .collect::<Vec<_>>()
-                                        .join("\n")
-                                    )
+    /// Takes the pinned pairs out of the alphabets, returning the letters left to choose from and how many pairs are still free
+    fn free_letters(left_letters: &[char], options: &SolverOptions) -> (Vec<char>, Vec<char>, usize) {
+        let free_left = left_letters.iter()
+            .filter(|l| !options.pinned.iter().any(|(pinned, _)| pinned == *l))
+            .copied()
+            .collect::<Vec<_>>();
+        let free_right = Transform::R_LETTERS.iter()
+            .filter(|r| !options.pinned.iter().any(|(_, pinned)| pinned == *r))
+            .copied()
+            .collect::<Vec<_>>();
+        (free_left, free_right, options.pairs - options.pinned.len())
+    }
+
+    /// Every allowed transform with this L side
+    fn with_left<'a>(left: &'a [char], free_right: &'a [char], options: &'a SolverOptions) -> impl Iterator<Item = Transform> + 'a {
+        // Given our L side of the transform, try all possible R-side combinations (and their orderings if asked to)
+        combinations(free_right, left.len())
+            .flat_map(move |right| -> Box<dyn Iterator<Item = Vec<char>>> {
+                match options.pairing {
+                    Pairing::Sorted => Box::new(std::iter::once(right)),
+                    Pairing::Permuted => Box::new(Permutations::new(right)),
+                }
             })
-            .collect();
-        
-        words
-    };
+            .filter_map(move |right| Transform::new(left, &right, &options.pinned, options.pairing))
+            .filter(move |transform| !transform.contains_any(&options.forbidden))
+    }
+}
 
-    println!("Puzzle: {}",PUZZLE);
-    println!("Loaded wordlist: {:?}",word_list.clone().into_iter().map(|(size,words)| (size,words.len())).collect::<HashMap<_,_>>());
+/// Why a search didn't finish
+#[derive(Debug)]
+pub enum SearchError {
+    /// The options don't make sense for the puzzle, see `SolverOptions::check`
+    Options(String),
+    /// Stopped through `Progress::cancel`. The L sides finished before then are in the checkpoint, if there is one
+    Cancelled,
+    /// The checkpoint couldn't be opened or written to
+    Checkpoint(String),
+    /// The search finished but the cache couldn't be written
+    Write(String),
+}
 
-    
-    let result = AllTransforms::parallel_map(|transform| {
-        
-        let result = transform.apply(PUZZLE.to_string());
-        let possibilities = get_possibilities(&result,&word_list);
-
-        if possibilities.iter().map(|x| x.len()).min().unwrap() > 0 {
-            Some((transform.into_hashmap(),possibilities))
-        } else {
-            None
-        }
-        //possibilities.sort_by(|a,b| a.len().cmp(&b.len()));
-        //possibility_histogram.push(&possibilities[0].len());
-        //possibilities[0].iter()
-        //    .for_each(|word| limiting_words.push(word));
-
-    });
-
-    result
-}
-
-fn get_possibilities(puzzle: &String, wordlist: &HashMap<usize,String>) -> Vec<Vec<String>> {
-    puzzle.split(' ')
-        .map(|word| {
-            let regex = Regex::new(format!(r"(?m)^{}$", word).as_str()).unwrap();
-            let possibilities : Vec<String> = regex
-                .find_iter(
-                wordlist.get(&word.len()).unwrap()
-                )
-                .map(|x| String::from(x.as_str()))
-                .collect();
-            possibilities
+impl std::fmt::Display for SearchError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SearchError::Cancelled => write!(f, "search cancelled"),
+            SearchError::Options(reason) | SearchError::Checkpoint(reason) | SearchError::Write(reason) => write!(f, "{}", reason),
+        }
+    }
+}
+
+impl std::error::Error for SearchError {}
+
+/// Searches every transform for ones that leave the puzzle solvable. With a checkpoint, L sides it already
+/// has are skipped and new ones are added to it as they finish; the result is the same either way.
+/// Progress is reported through `progress`, which can also be used to cancel the search
+pub fn get_all_solutions(puzzle: &Puzzle, options: &SolverOptions, dictionary: &Dictionary, mut checkpoint: Option<&mut Checkpoint>, progress: &Progress) -> Result<TransformAndPossibilitiesList, SearchError> {
+    let word_index = WordIndex::new(dictionary.words().iter().map(String::as_str));
+    let pattern_cache = PatternCache::new(&word_index);
+
+    let left_letters = puzzle.known_letters();
+    // the letters any transform could let into the blanks, for the checks that happen before there is a transform
+    let any_blanks = BlankRule::alphabet(&options.blanks, &left_letters, None);
+
+    let labels = Labels::new(puzzle);
+    let words = puzzle.words().collect::<Vec<_>>();
+    // roughly the most constrained words first, so hopeless transforms are thrown out early. The order is fixed from the
+    // untransformed words, which is only approximate once a transform changes them, but sorting each transform's words
+    // would mean looking every one of them up before bailing out
+    let mut order = (0..words.len()).collect::<Vec<_>>();
+    order.sort_by_key(|&i| pattern_cache.get(words[i], any_blanks).len());
+
+    let pinned_only = Transform::new(&[], &[], &options.pinned, Pairing::Permuted).unwrap();
+
+    let mut results = checkpoint.as_mut().map(|c| c.take_resumed()).unwrap_or_default();
+
+    let new_results = AllTransforms::parallel_map(&left_letters, options, checkpoint.as_deref(), progress,
+        |left| {
+            // Words without any of these letters come out the same whatever the R side is
+            // (sequential substitution can chain through the pinned pairs, so there they must avoid those too)
+            let viable = words.iter()
+                .filter(|word| match options.substitution {
+                    Substitution::Simultaneous => !word.chars().any(|c| left.contains(&c)),
+                    Substitution::Sequential => !word.chars().any(|c| left.contains(&c) || pinned_only.left.contains(&c)),
+                })
+                .all(|word| !pattern_cache.get(&pinned_only.apply(word.to_string(), options.substitution), any_blanks).is_empty());
+            if !viable {
+                progress.skip_one();
+            }
+            viable
+        },
+        |transform| {
+            let result = transform.apply(puzzle.text().to_string(), options.substitution);
+            let patterns = result.split(' ').collect::<Vec<_>>();
+            let map = transform.to_map();
+            let blanks = BlankRule::alphabet(&options.blanks, &left_letters, Some(&map));
+
+            let mut possibilities: Vec<Option<WordList>> = vec![None; patterns.len()];
+            for &i in &order {
+                let list = pattern_cache.get(patterns[i], blanks);
+                if list.is_empty() {
+                    // one impossible word sinks the whole sentence, no need to look at the rest
+                    return None;
+                }
+                possibilities[i] = Some(list);
+            }
+            let mut possibilities = possibilities.into_iter().map(Option::unwrap).collect::<Possibilities>();
+            // labelled blanks tie the words together, so narrow the lists until they agree on what each label could be
+            if !labels.is_empty() {
+                loop {
+                    let restrictions = labels.restrictions(&possibilities)?;
+                    if restrictions.iter().all(Vec::is_empty) {
+                        break;
+                    }
+                    for (list, restriction) in possibilities.iter_mut().zip(&restrictions).filter(|(_, r)| !r.is_empty()) {
+                        *list = pattern_cache.narrow(list, restriction);
+                        if list.is_empty() {
+                            return None;
+                        }
+                    }
+                }
+            }
+            Some((map,possibilities))
+        })?;
+
+    if progress.is_cancelled() {
+        return Err(SearchError::Cancelled);
+    }
+    progress.finish();
+    results.extend(new_results);
+    Ok(results.into_values().flatten().collect())
+}
+
+/// Remembers the words matching each pattern, since most transforms leave most puzzle words alone
+/// and the same pattern gets looked up over and over
+struct PatternCache<'a> {
+    index: &'a WordIndex,
+    lists: RwLock<HashMap<(String, Alphabet), WordList>>,
+    // lists cut down for labelled blanks
+    narrowed: RwLock<ListMemo<Vec<(usize, Alphabet)>>>,
+}
+
+impl<'a> PatternCache<'a> {
+    fn new(index: &'a WordIndex) -> PatternCache<'a> {
+        PatternCache {
+            index,
+            lists: RwLock::new(HashMap::new()),
+            narrowed: RwLock::new(ListMemo::new()),
+        }
+    }
+
+    /// The words of a list from this cache with only the given letters at the given indices
+    fn narrow(&self, list: &WordList, restrictions: &[(usize, Alphabet)]) -> WordList {
+        let key = restrictions.to_vec();
+        if let Some(narrowed) = self.narrowed.read().unwrap().get(list, &key) {
+            return narrowed;
+        }
+        let words = list.iter()
+            .filter(|word| restrictions.iter().all(|(index, letters)| word.as_bytes().get(*index).is_some_and(|b| letters.contains(*b as char))))
+            .cloned()
+            .collect::<Vec<_>>();
+        // another thread may have got there first, in which case theirs is kept
+        self.narrowed.write().unwrap().insert(list, key, words)
+    }
+
+    /// The words matching the pattern whose blanks are all in the alphabet
+    fn get(&self, pattern: &str, blanks: Alphabet) -> WordList {
+        let key = (pattern.to_string(), blanks);
+        if let Some(list) = self.lists.read().unwrap().get(&key) {
+            return list.clone();
+        }
+        let list = Arc::new(self.index.matches_with(pattern, blanks));
+        // another thread may have got there first, in which case use theirs so the list stays shared
+        self.lists.write().unwrap()
+            .entry(key)
+            .or_insert(list)
+            .clone()
+    }
+}
+
+/// Whether the rules let a transform's blanks hold letters the cached rules didn't, so the cache is missing words that
+/// narrowing can't bring back. Checked against the cached transforms, or the rules on their own if there are none
+pub fn blanks_looser(puzzle: &Puzzle, rules: &[BlankRule], cached: &[BlankRule], solutions: &TransformAndPossibilitiesList) -> bool {
+    let card = puzzle.known_letters();
+    let looser = |transform: Option<&TransformHash>| {
+        !BlankRule::alphabet(rules, &card, transform).is_subset(BlankRule::alphabet(cached, &card, transform))
+    };
+    if solutions.is_empty() {
+        return looser(None);
+    }
+    solutions.iter().any(|(transform, _)| looser(Some(transform)))
+}
+
+/// Narrows solutions loaded from a cache down to the blanks the rules allow, for when the rules are stricter
+/// than the ones the cache was built with. Transforms left with an empty position are dropped
+pub fn revalidate_blanks(puzzle: &Puzzle, rules: &[BlankRule], solutions: TransformAndPossibilitiesList) -> TransformAndPossibilitiesList {
+    let blanks = Blanks::new(puzzle);
+    let card = puzzle.known_letters();
+    // lists are shared between transforms, so filter each one once per alphabet
+    let mut filtered = ListMemo::new();
+    solutions.into_iter()
+        .filter_map(|(transform, possibilities)| {
+            let alphabet = BlankRule::alphabet(rules, &card, Some(&transform));
+            let possibilities = possibilities.iter()
+                .enumerate()
+                .map(|(position, list)| {
+                    filtered.edit(list, (position, alphabet), |list| {
+                        list.iter()
+                            .filter(|word| blanks.letters(position, word).all(|c| alphabet.contains(c)))
+                            .cloned()
+                            .collect()
+                    })
+                })
+                .collect::<Possibilities>();
+            possibilities.iter().all(|list| !list.is_empty()).then_some((transform, possibilities))
         })
         .collect()
 }
 
+/// Combinations::new panics unless k < n, so the edge cases are handled here
+fn combinations(items: &[char], k: usize) -> Box<dyn Iterator<Item = Vec<char>> + Send> {
+    match k {
+        0 => Box::new(std::iter::once(vec![])),
+        k if k == items.len() => {
+            // Combinations sorts its input, so do the same here
+            let mut items = items.to_vec();
+            items.sort();
+            Box::new(std::iter::once(items))
+        },
+        k if k > items.len() => Box::new(std::iter::empty()),
+        k => Box::new(Combinations::new(items.to_vec(), k)),
+    }
+}
+
+/// Every ordering of a list, in lexicographic order starting from the sorted one
+struct Permutations {
+    next: Option<Vec<char>>,
+}
+
+impl Permutations {
+    fn new(mut items: Vec<char>) -> Permutations {
+        items.sort();
+        Permutations { next: Some(items) }
+    }
+}
+
+impl Iterator for Permutations {
+    type Item = Vec<char>;
+
+    fn next(&mut self) -> Option<Vec<char>> {
+        let current = self.next.take()?;
+        let mut following = current.clone();
+        // standard next-permutation: find the rightmost ascent, swap in its successor and reverse the tail
+        if let Some(i) = following.windows(2).rposition(|w| w[0] < w[1]) {
+            let j = following.iter().rposition(|c| *c > following[i]).unwrap();
+            following.swap(i, j);
+            following[i + 1..].reverse();
+            self.next = Some(following);
+        }
+        Some(current)
+    }
+}
+
+/// Number of ways to choose k items from n, used to size the progress output
+fn binomial(n: usize, k: usize) -> usize {
+    if k > n {
+        return 0;
+    }
+    (0..k).fold(1, |acc, i| acc * (n - i) / (i + 1))
+}
+
 #[derive(Clone)]
-struct Transform<'a> {
+struct Transform {
     //mappings: [u8; 10],
-    left: &'a Vec<char>,
-    right: &'a Vec<char>,
+    left: Vec<char>,
+    right: Vec<char>,
 }
 
-impl<'a> Transform<'a> {
-    //tidronaeywlmc
-
-    const L_LETTERS : [char; 13] = ['t','i','d','r','o','n','a','e','y','w','l','m','c'];
+impl Transform {
     const R_LETTERS : [char; 26] = ['a','b','c','d','e','f','g','h','i','j','k','l','m','n','o','p','q','r','s','t','u','v','w','x','y','z'];
 
-    fn new(l: &'a Vec<char>, r: &'a Vec<char>) -> Transform<'a> {
-        Transform {
-            left: l,
-            right: r,
-        }
+    /// Builds a transform from the chosen letters plus the pinned pairs, or None if the pins don't fit.
+    /// Pairs are kept in order of their left letter, which is the order sequential substitution applies them in
+    fn new(l: &[char], r: &[char], pinned: &[(char,char)], pairing: Pairing) -> Option<Transform> {
+        let mut pairs = match pairing {
+            Pairing::Sorted => {
+                // both sides are sorted before zipping, so pins only fit if they land in the same slot
+                let mut left = [l, &pinned.iter().map(|(p, _)| *p).collect::<Vec<_>>()].concat();
+                let mut right = [r, &pinned.iter().map(|(_, p)| *p).collect::<Vec<_>>()].concat();
+                left.sort();
+                right.sort();
+                let pairs = std::iter::zip(left, right).collect::<Vec<_>>();
+                if !pinned.iter().all(|pin| pairs.contains(pin)) {
+                    return None;
+                }
+                pairs
+            },
+            Pairing::Permuted => std::iter::zip(l.iter().copied(), r.iter().copied())
+                .chain(pinned.iter().copied())
+                .collect(),
+        };
+        pairs.sort();
+        Some(Transform {
+            left: pairs.iter().map(|(l, _)| *l).collect(),
+            right: pairs.iter().map(|(_, r)| *r).collect(),
+        })
+    }
+
+    fn contains_any(&self, pairs: &[(char,char)]) -> bool {
+        std::iter::zip(self.left.iter(), self.right.iter())
+            .any(|(l, r)| pairs.contains(&(*l, *r)))
     }
 
-    fn apply(&self, mut str: String) -> String {
+    fn apply(&self, mut str: String, substitution: Substitution) -> String {
         let mut result = std::mem::take(&mut str).into_bytes();
         //println!("Applying transform: {}",self);
-        for t in std::iter::zip(self.left.iter(),self.right.iter()) {
-            for b in &mut result {
-                if *b == *t.0 as u8 {
-                    *b = *t.1 as u8
+        match substitution {
+            Substitution::Simultaneous => {
+                let mut table: [u8; 256] = std::array::from_fn(|i| i as u8);
+                for (l, r) in std::iter::zip(self.left.iter(),self.right.iter()) {
+                    table[*l as usize] = *r as u8;
                 }
-            }
-            //println!("Replacing {} with {}",*t.0,*t.1);
+                for b in &mut result {
+                    *b = table[*b as usize];
+                }
+            },
+            Substitution::Sequential => {
+                for t in std::iter::zip(self.left.iter(),self.right.iter()) {
+                    for b in &mut result {
+                        if *b == *t.0 as u8 {
+                            *b = *t.1 as u8
+                        }
+                    }
+                    //println!("Replacing {} with {}",*t.0,*t.1);
+                }
+            },
         }
         String::from_utf8(result).unwrap()
     }
 
-    fn into_hashmap(&self) -> HashMap<char,char>{
+    fn to_map(&self) -> TransformHash {
         std::iter::zip(
             self.left.iter(),
             self.right.iter())
@@ -163,13 +565,293 @@ impl<'a> Transform<'a> {
     
 }
 
-impl std::fmt::Display for Transform<'_> {
+impl std::fmt::Display for Transform {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> { 
         write!(f, "| ")?;
-        for i in 0..5 {
-            write!(f, "{}➔ {} | ", self.left[i], self.right[i])?;
+        for (l, r) in std::iter::zip(self.left.iter(), self.right.iter()) {
+            write!(f, "{}➔ {} | ", l, r)?;
         }
         Ok(())
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{dictionary_file, TempPath};
+    use regex::Regex;
+    use std::time::Instant;
+
+    fn transform(pairs: &[(char, char)]) -> Transform {
+        Transform::new(&[], &[], pairs, Pairing::Permuted).unwrap()
+    }
+
+    #[test]
+    fn simultaneous_swaps_letters() {
+        let swap = transform(&[('a', 'b'), ('b', 'a')]);
+        assert_eq!(swap.apply("abba cab".into(), Substitution::Simultaneous), "baab cba");
+        // a chain only moves each letter once
+        let chain = transform(&[('a', 'b'), ('b', 'c')]);
+        assert_eq!(chain.apply("abc".into(), Substitution::Simultaneous), "bcc");
+    }
+
+    #[test]
+    fn sequential_applies_pairs_in_order() {
+        let swap = transform(&[('a', 'b'), ('b', 'a')]);
+        assert_eq!(swap.apply("abba cab".into(), Substitution::Sequential), "aaaa caa");
+        // pairs go in order of their left letter, so a➔b is followed by b➔c
+        let chain = transform(&[('b', 'c'), ('a', 'b')]);
+        assert_eq!(chain.apply("abc".into(), Substitution::Sequential), "ccc");
+    }
+
+    #[test]
+    fn apply_leaves_blanks_and_other_letters() {
+        let t = transform(&[('t', 'h')]);
+        for substitution in [Substitution::Simultaneous, Substitution::Sequential] {
+            assert_eq!(t.apply("t.e c1t".into(), substitution), "h.e c1h");
+        }
+    }
+
+    #[test]
+    fn looser_blank_rules_are_spotted() {
+        let puzzle = Puzzle::new("th. c.t").unwrap();
+        let rules = |texts: &[&str]| texts.iter().map(|text| BlankRule::parse(text).unwrap()).collect::<Vec<_>>();
+        let solutions: TransformAndPossibilitiesList = vec![(BTreeMap::from([('t', 'a')]), vec![])];
+        let looser = |requested: &[&str], cached: &[&str]| blanks_looser(&puzzle, &rules(requested), &rules(cached), &solutions);
+
+        assert!(!looser(&["only:aei"], &["only:aeiou"]));
+        assert!(!looser(&["only:aeiou"], &["only:aeiou"]));
+        assert!(looser(&["only:aeiouy"], &["only:aeiou"]));
+        assert!(looser(&["except:x"], &["only:aeiou"]));
+        assert!(!looser(&["only:aeiou"], &[]));
+        // the transform only takes t off the card, so its left side is within anything allowing t
+        assert!(!looser(&["left"], &["only:st"]));
+        assert!(looser(&["left"], &["only:aeiou"]));
+        assert!(!looser(&["only:aeiou", "left"], &["only:aeiou"]));
+        // with nothing cached, only the rules themselves can be compared
+        assert!(blanks_looser(&puzzle, &rules(&["only:aeiouy"]), &rules(&["only:aeiou"]), &Vec::new()));
+        assert!(!blanks_looser(&puzzle, &rules(&["only:aei"]), &rules(&["only:aeiou"]), &Vec::new()));
+    }
+
+    #[test]
+    fn permutations_in_lexicographic_order() {
+        let all = Permutations::new(vec!['c', 'a', 'b']).map(String::from_iter).collect::<Vec<_>>();
+        assert_eq!(all, ["abc", "acb", "bac", "bca", "cab", "cba"]);
+        assert_eq!(Permutations::new(vec![]).count(), 1);
+        assert_eq!(Permutations::new(vec!['x']).collect::<Vec<_>>(), [vec!['x']]);
+        assert_eq!(Permutations::new("abcde".chars().collect()).count(), 120);
+    }
+
+    #[test]
+    fn permutations_of_repeated_letters_are_distinct() {
+        let all = Permutations::new(vec!['a', 'b', 'a']).map(String::from_iter).collect::<Vec<_>>();
+        assert_eq!(all, ["aab", "aba", "baa"]);
+    }
+
+    fn pairs(transform: &Transform) -> Vec<(char, char)> {
+        std::iter::zip(transform.left.iter().copied(), transform.right.iter().copied()).collect()
+    }
+
+    #[test]
+    fn sorted_pins_have_to_land_in_their_slot() {
+        // a and b sort before x and y, so b=y lines up with the rest
+        let fits = Transform::new(&['a'], &['x'], &[('b', 'y')], Pairing::Sorted).unwrap();
+        assert_eq!(pairs(&fits), [('a', 'x'), ('b', 'y')]);
+        // but w sorts before x, which would pair a=w and b=x
+        assert!(Transform::new(&['a'], &['x'], &[('b', 'w')], Pairing::Sorted).is_none());
+        // permuted pairing takes the pin as it is
+        let permuted = Transform::new(&['a'], &['x'], &[('b', 'w')], Pairing::Permuted).unwrap();
+        assert_eq!(pairs(&permuted), [('a', 'x'), ('b', 'w')]);
+    }
+
+    #[test]
+    fn every_pair_pinned_leaves_one_transform() {
+        let options = SolverOptions { pairs: 2, pinned: vec![('a', 'x'), ('b', 'y')], ..SolverOptions::default() };
+        let (free_left, free_right, free_pairs) = AllTransforms::free_letters(&['a', 'b', 'c'], &options);
+        assert_eq!(free_left, ['c']);
+        assert_eq!(free_right.len(), 24);
+        assert_eq!(free_pairs, 0);
+        let all = combinations(&free_left, free_pairs)
+            .flat_map(|left| AllTransforms::with_left(&left, &free_right, &options).collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+        assert_eq!(all.len(), 1);
+        assert_eq!(pairs(&all[0]), [('a', 'x'), ('b', 'y')]);
+    }
+
+    #[test]
+    fn transforms_keep_the_pins_and_leave_out_forbidden_pairs() {
+        for pairing in [Pairing::Sorted, Pairing::Permuted] {
+            let options = SolverOptions {
+                pairs: 3,
+                pairing,
+                pinned: vec![('c', 'm')],
+                forbidden: vec![('a', 'b'), ('b', 'n'), ('d', 'a')],
+                ..SolverOptions::default()
+            };
+            let (free_left, free_right, free_pairs) = AllTransforms::free_letters(&['a', 'b', 'c', 'd'], &options);
+            let all = combinations(&free_left, free_pairs)
+                .flat_map(|left| AllTransforms::with_left(&left, &free_right, &options).collect::<Vec<_>>())
+                .map(|transform| pairs(&transform))
+                .collect::<Vec<_>>();
+            assert!(!all.is_empty());
+            for transform in &all {
+                assert_eq!(transform.len(), 3);
+                assert!(transform.contains(&('c', 'm')), "{:?} is missing the pin", transform);
+                assert!(!transform.iter().any(|pair| options.forbidden.contains(pair)), "{:?} has a forbidden pair", transform);
+            }
+            // sorted pairing only keeps right sides that are in order with the pin in c's slot
+            let in_order = all.iter().filter(|t| t.windows(2).all(|w| w[0].1 < w[1].1)).count();
+            assert_eq!(in_order == all.len(), pairing == Pairing::Sorted);
+        }
+    }
+
+    #[test]
+    fn options_are_checked_against_the_puzzle() {
+        let puzzle = Puzzle::new("th. c.t").unwrap();
+        let check = |pairs: usize, pinned: &[(char, char)], forbidden: &[(char, char)]| {
+            SolverOptions { pairs, pinned: pinned.to_vec(), forbidden: forbidden.to_vec(), ..SolverOptions::default() }
+                .check(&puzzle)
+                .map_err(|e| e.to_string())
+        };
+        assert!(check(3, &[('t', 'a')], &[('h', 'e')]).is_ok());
+        assert!(check(4, &[], &[]).unwrap_err().contains("only has 3"));
+        assert!(check(1, &[('t', 'a'), ('h', 'e')], &[]).unwrap_err().contains("2 pairs are pinned"));
+        assert!(check(2, &[('x', 'a')], &[]).unwrap_err().contains("isn't on the card"));
+        assert!(check(2, &[('t', 'a'), ('t', 'e')], &[]).unwrap_err().contains("reuses a letter"));
+        assert!(check(2, &[('t', 'a'), ('h', 'a')], &[]).unwrap_err().contains("reuses a letter"));
+        assert!(check(2, &[('t', 'a')], &[('t', 'a')]).unwrap_err().contains("both pinned and forbidden"));
+    }
+
+    #[test]
+    fn impossible_labels_drop_the_transform() {
+        let path = TempPath::new("labels.txt");
+        let options = SolverOptions {
+            pairs: 1,
+            dictionaries: vec![dictionary_file(&path, "full", "ac\nbd\ned\n")],
+            ..SolverOptions::default()
+        };
+        let dictionary = Dictionary::load(&options.dictionaries);
+        let puzzle = Puzzle::new("a1 b1").unwrap();
+        let solutions = get_all_solutions(&puzzle, &options, &dictionary.unwrap(), None, &Progress::new()).unwrap();
+        // a->a gives ac and bd and b->e gives ac and ed, which each need 1 to be two letters at once
+        let mut found = solutions.iter()
+            .map(|(transform, lists)| (transform.iter().map(|(l, r)| (*l, *r)).collect::<Vec<_>>(), lists.iter().map(|list| list.to_vec()).collect::<Vec<_>>()))
+            .collect::<Vec<_>>();
+        found.sort();
+        assert_eq!(found, [
+            (vec![('a', 'b')], vec![vec!["bd".to_string()], vec!["bd".to_string()]]),
+            (vec![('a', 'e')], vec![vec!["ed".to_string()], vec!["bd".to_string()]]),
+            (vec![('b', 'a')], vec![vec!["ac".to_string()], vec!["ac".to_string()]]),
+        ]);
+    }
+
+    #[test]
+    fn impossible_left_sides_are_skipped() {
+        let path = TempPath::new("skip.txt");
+        let options = SolverOptions {
+            pairs: 1,
+            dictionaries: vec![dictionary_file(&path, "full", "ab\nqq\n")],
+            ..SolverOptions::default()
+        };
+        let dictionary = Dictionary::load(&options.dictionaries).unwrap();
+        let progress = Progress::new();
+        let solutions = get_all_solutions(&Puzzle::new("ab zz").unwrap(), &options, &dictionary, None, &progress).unwrap();
+        // taking a or b leaves zz as it is, which no word matches, so only z is searched
+        assert_eq!(solutions.iter().map(|(transform, _)| transform.clone()).collect::<Vec<_>>(), [TransformHash::from([('z', 'q')])]);
+        let snapshot = progress.snapshot();
+        assert_eq!((snapshot.total, snapshot.completed, snapshot.skipped), (3, 3, 2));
+    }
+
+    // the original matcher: a regex per word, run over the newline-joined words of the same length.
+    // Kept as the reference that the word index is benchmarked and checked against
+    fn get_possibilities_regex(puzzle: &str, wordlist: &HashMap<usize,String>) -> Vec<Vec<String>> {
+        puzzle.split(' ')
+            .map(|word| {
+                // no dictionary words of this length means nothing can fit here
+                let Some(candidates) = wordlist.get(&word.len()) else {
+                    return vec![];
+                };
+                let regex = Regex::new(format!(r"(?m)^{}$", word).as_str()).unwrap();
+                let possibilities : Vec<String> = regex
+                    .find_iter(candidates)
+                    .map(|x| String::from(x.as_str()))
+                    .collect();
+                possibilities
+            })
+            .collect()
+    }
+
+    /// Times the regex matcher against the word index on the first transforms of a search, and checks that both give
+    /// exactly the same possibilities. The regex reads a labelled blank as a literal digit, so labelled words are timed
+    /// but left out of the check. It needs the default dictionary, so run it by hand with something like
+    /// `JIPHER_BENCH_PUZZLE="th. c.t s.e .ow b.g" cargo test --release pattern_benchmark -- --ignored --nocapture`
+    #[test]
+    #[ignore]
+    fn pattern_benchmark() {
+        let puzzle = Puzzle::new(&std::env::var("JIPHER_BENCH_PUZZLE").unwrap_or("th. c.t s.e .ow b.g".to_string())).unwrap();
+        let samples = std::env::var("JIPHER_BENCH_SAMPLES").map_or(1000, |samples| samples.parse().unwrap());
+        let options = SolverOptions::default();
+        let dictionary = Dictionary::load(&options.dictionaries).unwrap();
+        let word_list = dictionary.words();
+        let regex_list = {
+            let mut buckets: HashMap<usize,Vec<&str>> = HashMap::new();
+            word_list.iter().for_each(|word| buckets.entry(word.len()).or_default().push(word));
+            buckets.into_iter()
+                .map(|(word_length, words)| (word_length, words.join("\n")))
+                .collect::<HashMap<usize,String>>()
+        };
+        let build_time = Instant::now();
+        let word_index = WordIndex::new(word_list.iter().map(String::as_str));
+        let build_time = build_time.elapsed();
+
+        let (free_left, free_right, free_pairs) = AllTransforms::free_letters(&puzzle.known_letters(), &options);
+        let puzzles = combinations(&free_left, free_pairs)
+            .flat_map(|left| {
+                AllTransforms::with_left(&left, &free_right, &options)
+                    .take(samples)
+                    .map(|transform| transform.apply(puzzle.text().to_string(), options.substitution))
+                    .collect::<Vec<_>>()
+            })
+            .take(samples)
+            .collect::<Vec<_>>();
+
+        let regex_time = Instant::now();
+        let from_regex = puzzles.iter().map(|p| get_possibilities_regex(p, &regex_list)).collect::<Vec<_>>();
+        let regex_time = regex_time.elapsed();
+
+        let index_time = Instant::now();
+        let from_index = puzzles.iter().map(|p| -> Vec<Vec<String>> {
+            p.split(' ').map(|word| word_index.matches(word)).collect()
+        }).collect::<Vec<_>>();
+        let index_time = index_time.elapsed();
+
+        let cache_time = Instant::now();
+        let pattern_cache = PatternCache::new(&word_index);
+        let from_cache = puzzles.iter().map(|p| -> Possibilities {
+            p.split(' ').map(|word| pattern_cache.get(word, Alphabet::ALL)).collect()
+        }).collect::<Vec<_>>();
+        let cache_time = cache_time.elapsed();
+
+        println!("{} transformed puzzles, {} patterns", puzzles.len(), puzzles.len() * puzzle.words().count());
+        println!("  regex: {:>10.3?}", regex_time);
+        println!("  index: {:>10.3?} (plus {:.3?} to build the index)", index_time, build_time);
+        println!("  index with pattern cache: {:>10.3?} ({} distinct patterns)", cache_time, pattern_cache.lists.read().unwrap().len());
+        println!("  speedup: {:.1}x, {:.1}x with the pattern cache",
+            regex_time.as_secs_f64() / index_time.as_secs_f64().max(f64::EPSILON),
+            regex_time.as_secs_f64() / cache_time.as_secs_f64().max(f64::EPSILON));
+
+        let checked = puzzle.words().map(|word| !word.bytes().any(|b| b.is_ascii_digit())).collect::<Vec<_>>();
+        if checked.contains(&false) {
+            println!("  labelled words aren't checked against the regex");
+        }
+        for ((p, regex), (index, cache)) in puzzles.iter().zip(&from_regex).zip(from_index.iter().zip(&from_cache)) {
+            for (((regex, index), cache), checked) in regex.iter().zip(index).zip(cache).zip(&checked) {
+                if *checked {
+                    assert_eq!(regex, index, "regex and index disagree on {}", p);
+                    assert_eq!(regex, cache.as_ref(), "regex and pattern cache disagree on {}", p);
+                }
+            }
+        }
+    }
+}
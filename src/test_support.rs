@@ -0,0 +1,44 @@
+//! Fixtures shared by the unit tests
+use std::path::PathBuf;
+use std::sync::Arc;
+use crate::dictionary::DictionarySpec;
+use crate::solutions::WordList;
+
+pub fn list(words: &[&str]) -> WordList {
+    Arc::new(words.iter().map(|word| word.to_string()).collect())
+}
+
+/// A list for each position of a sentence
+pub fn lists(lists: &[&[&str]]) -> Vec<WordList> {
+    lists.iter().map(|words| list(words)).collect()
+}
+
+/// A path in the temp directory that's deleted again when this is dropped, even if the test panics.
+/// Tests run in parallel, so each one needs a name of its own
+pub struct TempPath(PathBuf);
+
+impl TempPath {
+    pub fn new(name: &str) -> TempPath {
+        TempPath(std::env::temp_dir().join(format!("jipher-{}-{}", std::process::id(), name)))
+    }
+
+    pub fn as_str(&self) -> &str {
+        self.0.to_str().unwrap()
+    }
+
+    pub fn exists(&self) -> bool {
+        self.0.exists()
+    }
+}
+
+impl Drop for TempPath {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+/// Writes the words, one per line, to `path` and gives back a dictionary of the given tier reading them
+pub fn dictionary_file(path: &TempPath, tier: &str, words: &str) -> DictionarySpec {
+    std::fs::write(&path.0, words).unwrap();
+    DictionarySpec::parse(&format!("{}:{}", tier, path.as_str())).unwrap()
+}
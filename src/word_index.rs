@@ -0,0 +1,173 @@
+use std::collections::HashMap;
+use crate::alphabet::Alphabet;
+
+/// Answers "which words match t..rt." without building a regex.
+/// Words are bucketed by length, and every (position, letter) in a bucket has a bitset of the words that have
+/// that letter at that position, so a pattern is just the AND of the bitsets for its known letters.
+/// Matches come back in dictionary order, the same as scanning the word list with a regex would give.
+pub struct WordIndex {
+    buckets: HashMap<usize, Bucket>,
+}
+
+struct Bucket {
+    words: Vec<String>,
+    // letters[position][letter] has bit n set when words[n] has that letter at that position
+    letters: Vec<HashMap<u8, Vec<u64>>>,
+}
+
+impl Bucket {
+    fn new(words: Vec<String>, length: usize) -> Bucket {
+        let blocks = words.len().div_ceil(64);
+        let mut letters: Vec<HashMap<u8, Vec<u64>>> = (0..length).map(|_| HashMap::new()).collect();
+        for (n, word) in words.iter().enumerate() {
+            for (position, letter) in word.bytes().enumerate() {
+                letters[position].entry(letter).or_insert_with(|| vec![0; blocks])[n / 64] |= 1 << (n % 64);
+            }
+        }
+        Bucket { words, letters }
+    }
+
+    fn all(&self) -> Vec<u64> {
+        let mut bits = vec![u64::MAX; self.words.len().div_ceil(64)];
+        let remainder = self.words.len() % 64;
+        if remainder != 0 {
+            *bits.last_mut().unwrap() = (1 << remainder) - 1;
+        }
+        bits
+    }
+}
+
+impl WordIndex {
+    pub fn new<'a>(words: impl IntoIterator<Item = &'a str>) -> WordIndex {
+        let mut by_length: HashMap<usize, Vec<String>> = HashMap::new();
+        words.into_iter()
+            .filter(|word| !word.is_empty())
+            .for_each(|word| by_length.entry(word.len()).or_default().push(word.to_string()));
+
+        WordIndex {
+            buckets: by_length.into_iter()
+                .map(|(length, words)| (length, Bucket::new(words, length)))
+                .collect(),
+        }
+    }
+
+    /// Every word matching the pattern, where '.' matches any letter. Digits are labelled blanks: blanks with the
+    /// same digit hold the same letter and blanks with different digits hold different letters
+    #[cfg(test)]
+    pub fn matches(&self, pattern: &str) -> Vec<String> {
+        self.matches_with(pattern, Alphabet::ALL)
+    }
+
+    /// Every word matching the pattern, where blanks match any letter of the alphabet
+    pub fn matches_with(&self, pattern: &str, blanks: Alphabet) -> Vec<String> {
+        let Some(bucket) = self.buckets.get(&pattern.len()) else {
+            return vec![];
+        };
+
+        let mut bits = bucket.all();
+        for (position, letter) in pattern.bytes().enumerate() {
+            if letter == b'.' || letter.is_ascii_digit() {
+                if blanks != Alphabet::ALL {
+                    // a blank is the union of its allowed letters
+                    let mut allowed = vec![0u64; bits.len()];
+                    for with_letter in blanks.letters().filter_map(|c| bucket.letters[position].get(&(c as u8))) {
+                        allowed.iter_mut().zip(with_letter).for_each(|(a, w)| *a |= w);
+                    }
+                    bits.iter_mut().zip(&allowed).for_each(|(b, a)| *b &= a);
+                }
+                continue;
+            }
+            let Some(with_letter) = bucket.letters[position].get(&letter) else {
+                return vec![];
+            };
+            bits.iter_mut().zip(with_letter).for_each(|(b, w)| *b &= w);
+        }
+
+        let mut result = vec![];
+        for (block, mut b) in bits.into_iter().enumerate() {
+            while b != 0 {
+                let word = &bucket.words[block * 64 + b.trailing_zeros() as usize];
+                if labels_agree(pattern, word) {
+                    result.push(word.clone());
+                }
+                b &= b - 1;
+            }
+        }
+        result
+    }
+}
+
+/// Whether the word fills the pattern's labelled blanks consistently
+fn labels_agree(pattern: &str, word: &str) -> bool {
+    let mut seen: Vec<(u8, u8)> = vec![];
+    for (label, letter) in pattern.bytes().zip(word.bytes()).filter(|(label, _)| label.is_ascii_digit()) {
+        if seen.iter().any(|(l, c)| (*l == label) != (*c == letter)) {
+            return false;
+        }
+        seen.push((label, letter));
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use regex::Regex;
+
+    /// The regex matcher the index replaced, with blanks limited to the given letters
+    fn reference(words: &[String], pattern: &str, blanks: &str) -> Vec<String> {
+        let regex = Regex::new(&format!("^{}$", pattern.replace('.', &format!("[{}]", blanks)))).unwrap();
+        words.iter().filter(|word| regex.is_match(word)).cloned().collect()
+    }
+
+    fn words() -> Vec<String> {
+        // over a hundred four letter words so the bitsets need more than one block
+        let mut words: Vec<String> = "abc".chars()
+            .flat_map(|a| "aeiou".chars().map(move |b| (a, b)))
+            .flat_map(|(a, b)| "rstln".chars().map(move |c| (a, b, c)))
+            .flat_map(|(a, b, c)| "ey".chars().map(move |d| format!("{a}{b}{c}{d}")))
+            .collect();
+        words.extend(["a", "i", "at", "to", "the", "cat", "cot", "tot", "eye", "sentence"].map(String::from));
+        words
+    }
+
+    #[test]
+    fn matches_agree_with_regex() {
+        let words = words();
+        let index = WordIndex::new(words.iter().map(String::as_str));
+        let patterns = [
+            ".", "a", "..", ".t", "t.", "...", "c.t", ".o.", "t..", "..e", "....", "b...", "...y", ".i..", "c.ny",
+            // no words of these lengths
+            ".....", "......x", "",
+            // letters no word has
+            "q...", "..z",
+        ];
+        for pattern in patterns {
+            assert_eq!(index.matches(pattern), reference(&words, pattern, "a-z"), "pattern {pattern:?}");
+            assert_eq!(index.matches_with(pattern, Alphabet::of("aet".chars())), reference(&words, pattern, "aet"),
+                "pattern {pattern:?} with blanks aet");
+        }
+    }
+
+    #[test]
+    fn matches_cross_block_boundary() {
+        let words = words();
+        let index = WordIndex::new(words.iter().map(String::as_str));
+        let four = words.iter().filter(|word| word.len() == 4).count();
+        assert!(four > 128);
+        assert_eq!(index.matches("....").len(), four);
+        // the last words of the bucket sit in the final, partly filled block
+        assert_eq!(index.matches("cu.y"), reference(&words, "cu.y", "a-z"));
+        assert_eq!(index.matches_with("....", Alphabet::of(['c', 'u', 'n', 'y'])), vec!["cuny".to_string()]);
+    }
+
+    #[test]
+    fn labelled_blanks_agree() {
+        let index = WordIndex::new(["tot", "toe", "eye", "did", "dad", "add"]);
+        assert_eq!(index.matches("1.1"), ["tot", "eye", "did", "dad"]);
+        assert_eq!(index.matches("12."), ["tot", "toe", "eye", "did", "dad", "add"]);
+        assert_eq!(index.matches("121"), ["tot", "eye", "did", "dad"]);
+        assert_eq!(index.matches("112"), Vec::<String>::new());
+        assert_eq!(index.matches("1d2"), ["add"]);
+    }
+}
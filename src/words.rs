@@ -1,6 +1,11 @@
-use std::{collections::HashSet, time::Duration, u64};
+use std::{collections::HashSet, sync::Arc, time::Duration};
+use crate::constraints::{Blanks, Constraint};
+use crate::dictionary::{Tier, WordInfo};
 use crate::histogram::Histogram;
-use crate::solutions::TransformAndPossibilitiesList;
+use crate::labels::Labels;
+use crate::list_memo::ListMemo;
+use crate::puzzle::Puzzle;
+use crate::solutions::{TransformAndPossibilitiesList, TransformHash, WordList};
 /*
 type TransformHash = HashMap<char,char>;
 type Possibilities = Vec<Vec<String>>;
@@ -13,37 +18,102 @@ enum UpdatableValue<T> {
     Updating,
     Invalid,
 }
+/// How the word lists are sorted
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum WordOrder {
+    /// Most combinations first
+    Combinations,
+    /// Lowest dictionary rank first
+    Commonness,
+}
+
+impl std::fmt::Display for WordOrder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            WordOrder::Combinations => write!(f, "combinations"),
+            WordOrder::Commonness => write!(f, "commonness"),
+        }
+    }
+}
+
+// transforms with at most this many sentences are counted exactly when there are constraints to check,
+// bigger ones are estimated from a sample
+const EXACT_COUNT_LIMIT: u64 = 2000;
+const COUNT_SAMPLES: u64 = 200;
+
 pub struct Words {
+    puzzle: Puzzle,
+    info: WordInfo,
     state: TransformAndPossibilitiesList,
+    blanks: Blanks,
+    constraints: Vec<Constraint>,
     word_hist: Histogram<String>,//UpdatableValue<Histogram<String>>
     total_combinations: u64,
+    combinations_estimated: bool,
     critical_words: Histogram<String>,
     positional_word_hists: Vec<Histogram<String>>,
     history: Vec<String>,
 }
 
 impl Words {
-    pub fn new(data: TransformAndPossibilitiesList) -> Words {
+    pub fn new(puzzle: Puzzle, data: TransformAndPossibilitiesList, info: WordInfo) -> Words {
 
+        let labels = Labels::new(&puzzle);
         let mut me = Words {
+            blanks: Blanks::new(&puzzle),
+            puzzle,
+            info,
             state: data,
+            // labelled blanks are part of the puzzle, so they're always in force
+            constraints: if labels.is_empty() { vec![] } else { vec![Constraint::LabelledBlanks(labels)] },
             word_hist: Histogram::new(),//UpdatableValue::Invalid,
             total_combinations: 0,
+            combinations_estimated: false,
             critical_words: Histogram::new(),
             positional_word_hists: vec![],
             history: vec![],
         };
 
-        println!("Calculating stats on possiblities...");
         me.update_all();
         me
     }
 
+    pub fn puzzle(&self) -> &Puzzle {
+        &self.puzzle
+    }
+
+    /// The transforms and word lists left after the eliminations so far
+    pub fn state(&self) -> &TransformAndPossibilitiesList {
+        &self.state
+    }
+
+    /// Which dictionary tier a word came from
+    pub fn tier(&self, word: &str) -> Tier {
+        self.info.tier(word)
+    }
+
+    /// How common a word is according to the dictionary order, 1 being the most common
+    pub fn rank(&self, word: &str) -> Option<usize> {
+        self.info.rank(word)
+    }
+
+    /// The words in a histogram in the given order. Words without a rank go after the ranked ones
+    pub fn sorted(&self, hist: &Histogram<String>, order: WordOrder) -> Vec<(String,u64)> {
+        let mut words = hist.most_popular();
+        if order == WordOrder::Commonness {
+            // stable, so words of equal rank stay in count order
+            words.sort_by_key(|(word, _)| self.rank(word).unwrap_or(usize::MAX));
+        }
+        words
+    }
+
     pub fn word_hist(&self) -> &Histogram<String> {
         &self.word_hist
     }
 
     fn update_all(&mut self) {
+        self.propagate();
+
         //second pass to remove any transforms that have no possible complete sentences
         self.state
         .retain(|(_transform, words)| {
@@ -51,21 +121,19 @@ impl Words {
         });
 
         //probably could be much-optimized but hasn't been necessary so far, so a brute-force update is what you get
-        self.update_word_hist();
-        self.update_total_combinations();
+        let counts = self.update_total_combinations();
+        self.update_word_hist(&counts);
         self.update_critical_words();
         self.update_positional_word_hist();
     }
 
-    fn update_word_hist(&mut self) {
+    fn update_word_hist(&mut self, counts: &[u64]) {
         let mut word_hist = Histogram::new();
-        self.state.iter()
-        .for_each(|(_,possibilities)| {
-            let number_of_possibilities : usize = possibilities.iter().map(|v| v.len()).product();
-            for i in 0..possibilities.len() {
-                for j in 0..possibilities[i].len() {
-                    let word = &possibilities[i][j];
-                    let increment = (number_of_possibilities/possibilities[i].len()) as u64;
+        self.state.iter().zip(counts)
+        .for_each(|((_,possibilities), number_of_possibilities)| {
+            for word_options in possibilities {
+                for word in word_options.iter() {
+                    let increment = number_of_possibilities/word_options.len() as u64;
                     word_hist.push_multiple(word, increment);
                 }
             }
@@ -78,7 +146,7 @@ impl Words {
     }
 
     fn update_positional_word_hist(&mut self) {
-        let mut word_hists : Vec<Histogram<String>> = (0..self.state[0].1.len()).map(|_| Histogram::new()).collect();
+        let mut word_hists : Vec<Histogram<String>> = (0..self.sentence_length()).map(|_| Histogram::new()).collect();
         self.state.iter()
             .for_each(|(_,sentence)| {
                 for (i,wordlist) in sentence.iter().enumerate() {
@@ -88,9 +156,8 @@ impl Words {
         self.positional_word_hists = word_hists;
     }
 
-    pub fn get_top(&self, range: std::ops::Range<usize>, filter: impl FnMut(&(String,u64)) -> bool) -> Vec<(String,u64)> {
-        self.word_hist()
-            .most_popular()
+    pub fn get_top(&self, range: std::ops::Range<usize>, order: WordOrder, filter: impl FnMut(&(String,u64)) -> bool) -> Vec<(String,u64)> {
+        self.sorted(self.word_hist(), order)
             .into_iter().filter(filter).skip(range.start).take(range.end - range.start).collect()
     }       
 
@@ -98,31 +165,170 @@ impl Words {
         self.total_combinations
     }
 
-    fn update_total_combinations(&mut self) {
-        self.total_combinations = self.state.iter()
-            .map(|(_transform, words)| {
-                words.iter()
+    /// Whether the total is estimated, which happens when there are constraints the lists can't enforce on their own
+    pub fn combinations_estimated(&self) -> bool {
+        self.combinations_estimated
+    }
+
+    /// Counts the sentences of every transform that pass the constraints, dropping the transforms that are
+    /// certain to have none. Returns each remaining transform's count
+    fn update_total_combinations(&mut self) -> Vec<u64> {
+        let checked = self.constraints.iter().filter(|constraint| !constraint.enforced_by_lists()).collect::<Vec<_>>();
+        let counts = self.state.iter()
+            .enumerate()
+            .map(|(i, (transform, words))| {
+                let combinations = words.iter()
                     .map(|word_possibilities| word_possibilities.len() as u64)
-                    .product::<u64>()
+                    .product::<u64>();
+                if checked.is_empty() {
+                    (combinations, false)
+                } else if let [constraint] = checked[..] && let Some(count) = constraint.count(transform, words, &self.blanks) {
+                    (count, false)
+                } else if combinations <= EXACT_COUNT_LIMIT {
+                    (self.count_passing(transform, words, &checked), false)
+                } else {
+                    (self.estimate_passing(transform, words, &checked, i as u64), true)
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let mut counted = counts.iter();
+        self.state.retain(|_| counted.next().is_some_and(|(count, estimated)| *count > 0 || *estimated));
+        let counts = counts.into_iter().filter(|(count, estimated)| *count > 0 || *estimated).collect::<Vec<_>>();
+
+        self.total_combinations = counts.iter().map(|(count, _)| count).sum();
+        self.combinations_estimated = counts.iter().any(|(_, estimated)| *estimated);
+        counts.into_iter().map(|(count, _)| count).collect()
+    }
+
+    /// Goes through every sentence of a transform
+    fn count_passing(&self, transform: &TransformHash, words: &[WordList], checked: &[&Constraint]) -> u64 {
+        let mut choice = vec![0; words.len()];
+        let mut passing = 0;
+        loop {
+            let sentence = choice.iter().zip(words).map(|(i, list)| list[*i].as_str()).collect::<Vec<_>>();
+            if checked.iter().all(|constraint| constraint.check(transform, &sentence, &self.blanks)) {
+                passing += 1;
+            }
+            // odometer style, the last position turning fastest
+            let Some(position) = (0..words.len()).rev().find(|position| choice[*position] + 1 < words[*position].len()) else {
+                return passing;
+            };
+            choice[position] += 1;
+            choice[position + 1..].iter_mut().for_each(|i| *i = 0);
+        }
+    }
+
+    /// Scales up how many of a random sample of a transform's sentences pass. Seeded so the same state always gives the same estimate
+    fn estimate_passing(&self, transform: &TransformHash, words: &[WordList], checked: &[&Constraint], seed: u64) -> u64 {
+        let combinations = words.iter().map(|list| list.len() as u64).product::<u64>();
+        // xorshift, nothing fancy is needed here
+        let mut state = seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1;
+        let mut next = |below: usize| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            (state % below as u64) as usize
+        };
+        let passing = (0..COUNT_SAMPLES)
+            .filter(|_| {
+                let sentence = words.iter().map(|list| list[next(list.len())].as_str()).collect::<Vec<_>>();
+                checked.iter().all(|constraint| constraint.check(transform, &sentence, &self.blanks))
             })
-            .sum::<u64>()
+            .count() as u64;
+        (combinations as u128 * passing as u128 / COUNT_SAMPLES as u128) as u64
     }
 
+    /// Whether a sentence from this transform passes every constraint
+    pub fn allows(&self, transform: &TransformHash, sentence: &[&str]) -> bool {
+        self.constraints.iter().all(|constraint| constraint.check(transform, sentence, &self.blanks))
+    }
+
+    /// `allows` on its own, for checking sentences somewhere `Words` can't go, like another thread
+    pub fn checker(&self) -> impl Fn(&TransformHash, &[&str]) -> bool + Send + Sync + 'static {
+        let constraints = self.constraints.clone();
+        let blanks = Blanks::new(&self.puzzle);
+        move |transform, sentence| constraints.iter().all(|constraint| constraint.check(transform, sentence, &blanks))
+    }
+
+    pub fn blanks(&self) -> &Blanks {
+        &self.blanks
+    }
+
+    pub fn constraints(&self) -> &[Constraint] {
+        &self.constraints
+    }
+
+    /// Adds a rule about whole sentences, pruning the lists with it straight away
+    pub fn add_constraint(&mut self, constraint: Constraint) {
+        if self.constraints.contains(&constraint) {
+            return;
+        }
+        self.history.push(format!("Rule: {}", constraint));
+        self.history.rotate_right(1);
+        self.constraints.push(constraint);
+
+        self.update_all();
+    }
+
+    /// Prunes each transform's lists with every constraint until none of them can remove anything else
+    fn propagate(&mut self) {
+        if self.constraints.is_empty() {
+            return;
+        }
+        let mut pruned = ListMemo::new();
+        for (transform, sentence) in &mut self.state {
+            let mut changed = true;
+            while changed && sentence.iter().all(|list| !list.is_empty()) {
+                changed = false;
+                for constraint in &self.constraints {
+                    for (position, prune) in constraint.prune(transform, sentence, &self.blanks) {
+                        let list = &mut sentence[position];
+                        let new_list = pruned.edit(list, (position, prune.clone()), |list| {
+                            list.iter().filter(|word| prune.keeps(word, position, &self.blanks)).cloned().collect()
+                        });
+                        if !Arc::ptr_eq(&new_list, list) {
+                            *list = new_list;
+                            changed = true;
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Transforms left
     pub fn total_transforms(&self) -> usize {
-        return self.state.len();
+        self.state.len()
+    }
+
+    /// Edits the word lists at every position (or just one), editing each shared list only once so that
+    /// transforms sharing a list keep sharing it. Edits may only remove words
+    fn edit_lists(&mut self, position: Option<usize>, edit: impl Fn(&mut Vec<String>)) {
+        let mut edited = ListMemo::new();
+        for (_transform, sentence) in &mut self.state {
+            for (i, list) in sentence.iter_mut().enumerate() {
+                if position.is_some_and(|p| p != i) {
+                    continue;
+                }
+                *list = edited.edit(list, (), |list| {
+                    let mut words = list.to_vec();
+                    edit(&mut words);
+                    words
+                });
+            }
+        }
     }
 
     pub fn remove_words(&mut self, words: Vec<String>) {
         let word_list = words.join(",");
-        for word in words {
-            for (_transform, words) in &mut self.state {
-                for word_list in words {
-                    if let Some(pos) = word_list.iter().position(|w| *w == *word) {
-                        word_list.swap_remove(pos);
-                    }
+        self.edit_lists(None, |list| {
+            for word in &words {
+                if let Some(pos) = list.iter().position(|w| *w == *word) {
+                    list.swap_remove(pos);
                 }
             }
-        }
+        });
         
         self.update_all();
 
@@ -133,15 +339,15 @@ impl Words {
     pub fn remove_words_positional(&mut self, words: Vec<String>, position: usize) {
         let word_list = words.join(",");
 
-        if position > self.state[0].1.len() { panic!(); }
+        if position >= self.sentence_length() { panic!(); }
 
-        for target_word in words {
-            for (_transform, sentences) in &mut self.state {
-                if let Some(pos) = sentences[position].iter().position(|w| *w == *target_word) {
-                    sentences[position].swap_remove(pos);
+        self.edit_lists(Some(position), |list| {
+            for target_word in &words {
+                if let Some(pos) = list.iter().position(|w| *w == *target_word) {
+                    list.swap_remove(pos);
                 }
             }
-        }
+        });
 
         self.update_all();
 
@@ -162,17 +368,15 @@ impl Words {
     }
 
     pub fn require_word_positional(&mut self, target_word: String, position: usize) {
-        self.state.iter_mut().for_each(|(_,sentence)| {
-            sentence[position].retain(|word| word == &target_word)
-        });
+        self.edit_lists(Some(position), |list| list.retain(|word| word == &target_word));
 
         self.update_all();
         self.history.push(format!("+ Word {}: {}", position, target_word));
         self.history.rotate_right(1);
     }
 
-    pub fn critical_words(&self) -> Vec<(String,u64)> {
-        self.critical_words.most_popular()
+    pub fn critical_words(&self, order: WordOrder) -> Vec<(String,u64)> {
+        self.sorted(&self.critical_words, order)
     }
 
     fn update_critical_words(&mut self) {
@@ -183,7 +387,7 @@ impl Words {
                     if words.len() == 1 {
                         self.critical_words.push(&words[0]);
                     }
-                    if words.len() == 0 {
+                    if words.is_empty() {
                         panic!("empty wordlist found!");
                     }
                 });
@@ -193,7 +397,7 @@ impl Words {
     pub fn total_words(&self) -> usize {        
         return self.state.iter()
             .flat_map(|(_transform,sentences)| {
-                sentences.iter().flatten()
+                sentences.iter().flat_map(|words| words.iter())
             })
             .collect::<HashSet<&String>>()
             .len()
@@ -209,42 +413,37 @@ impl Words {
             let range = to - from;
             ((rand % range as u128) + from as u128).try_into().unwrap_or(0)
         }
-        let i = bad_rand(0, self.state.len());
-        let (transform,sentence) = self.state[i].clone();
-        (
-            format!("{:?}",transform)
-            ,sentence.iter()
-            .map(|wordlist| {
-                let j = bad_rand(0, wordlist.len());
-                wordlist[j].clone() + " "
-            })
-            .collect::<String>()
-        )
+        if self.state.is_empty() {
+            return (String::new(), String::new());
+        }
+        // the lists can hold sentences the constraints rule out, so have a few goes at finding one that passes
+        for _ in 0..100 {
+            let i = bad_rand(0, self.state.len());
+            let (transform,sentence) = &self.state[i];
+            let words = sentence.iter()
+                .map(|wordlist| wordlist[bad_rand(0, wordlist.len())].as_str())
+                .collect::<Vec<_>>();
+            if self.allows(transform, &words) {
+                return (
+                    format!("{:?}",transform),
+                    words.iter().map(|word| word.to_string() + " ").collect::<String>()
+                );
+            }
+        }
+        // better to say so than show a sentence the rules throw out
+        (String::new(), "(no sentence found that passes the rules)".to_string())
     }
 
     pub fn sentence_length(&self) -> usize {
-        if let Some((_,sentences)) = self.state.get(0) {
-            sentences.len()
-        } else {
-            0
-        }
+        self.puzzle.words().count()
+    }
+
+    /// How many edits have been made, for telling whether something worked out from the state is out of date
+    pub fn edits(&self) -> usize {
+        self.history.len()
     }
 
     pub fn history(&self) -> String {
         self.history.clone().join("\n")
     }
 }
-
-
-
-
-
-
-
-
-
-
-
-
-
-